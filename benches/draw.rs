@@ -0,0 +1,92 @@
+// Times rendering a full frame with made up readings, for budgeting the
+// refresh interval on slow hardware: `cargo bench`, or build it with
+// `cargo bench --no-run` and copy the binary over to the Pi
+use malter::{Config, Data, DayForecast, ForecastData, IndoorData, OutdoorData, Trend};
+
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use chrono::prelude::*;
+
+const ITERATIONS: u32 = 100;
+
+fn fake_data() -> Data {
+    let today = Local::today().naive_local();
+    let day = |days: i64, condition: &str, condition_id: u32| DayForecast {
+        date: Some(today + chrono::Duration::days(days)),
+        high: 12.0,
+        low: -3.5,
+        condition: condition.to_string(),
+        condition_id: Some(condition_id),
+        pop: 0.4,
+        wind_speed: 4.2,
+        wind_deg: 270.0,
+    };
+    Data {
+        indoor: vec![IndoorData {
+            label: "Kitchen".to_string(),
+            sampled_at: Some(Utc::now()),
+            temp: Some(21.5),
+            humidity: Some(40.0),
+            pressure: Some(1013.0),
+            dewpoint: Some(7.5),
+            co2: None,
+            voc: None,
+        }],
+        outdoor: Some(OutdoorData {
+            temp: Some(-12.3),
+            humidity: Some(80.0),
+            pressure: Some(1001.0),
+            sunrise: None,
+            sunset: None,
+            aqi: None,
+            uvi: Some(3.0),
+        }),
+        forecast: Some(ForecastData {
+            days: vec![
+                day(0, "Snow", 601),
+                day(1, "Clouds", 803),
+                day(2, "Clear", 800),
+            ],
+            moon_phase: Some(0.3),
+        }),
+        battery: None,
+        indoor_history: (0..48).map(|i| 20.0 + (i as f64 / 5.0).sin()).collect(),
+        pressure_trend: Some(Trend::Steady),
+    }
+}
+
+fn main() -> malter::Result<()> {
+    let config = Config::load(concat!(env!("CARGO_MANIFEST_DIR"), "/conf-sample.toml"))?;
+    let data = fake_data();
+    let now = Local::now();
+
+    // the first frame pays for anything lazy, so it isn't counted
+    black_box(malter::render(&config, &data, now)?);
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        black_box(malter::render(&config, black_box(&data), now)?);
+    }
+    report("render", start.elapsed());
+
+    // the daemon's way, one frame drawn into over and over
+    let mut frame = Vec::new();
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        malter::render_into(&config, black_box(&data), now, &mut frame)?;
+        black_box(&frame);
+    }
+    report("render_into", start.elapsed());
+    Ok(())
+}
+
+fn report(name: &str, elapsed: Duration) {
+    println!(
+        "{}: {:?} per frame over {} frames",
+        name,
+        elapsed / ITERATIONS,
+        ITERATIONS
+    );
+}
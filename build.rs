@@ -0,0 +1,20 @@
+// Bakes the git hash into the binary for --version (not as MALTER_*, cargo run
+// passes it on and it would be taken for a config key), the same short hash
+// deploy.sh names the copies on the Pi after. "unknown" outside a checkout.
+use std::process::Command;
+
+fn main() {
+    let hash = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|hash| hash.trim().to_string())
+        .filter(|hash| !hash.is_empty())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=GIT_HASH={}", hash);
+    // HEAD moves on a checkout, the branch it points at on a commit
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs/heads");
+}
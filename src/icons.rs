@@ -0,0 +1,55 @@
+// 16x16 one bit weather icons, a row per two bytes with the leftmost pixel
+// in the high bit and set bits drawn black
+
+// Size of every icon in pixels, they're square
+pub const ICON_SIZE: u32 = 16;
+
+// The icon for an OpenWeather condition code, see
+// https://openweathermap.org/weather-conditions
+pub fn icon_for_condition(id: u32) -> &'static [u8] {
+    match id {
+        200..=299 => &ICON_THUNDER,
+        300..=399 | 500..=510 | 520..=599 => &ICON_RAIN,
+        // freezing rain looks more like snow by the time it lands
+        511 | 600..=699 => &ICON_SNOW,
+        700..=799 => &ICON_FOG,
+        800 => &ICON_CLEAR,
+        801..=899 => &ICON_CLOUDY,
+        _ => &ICON_UNKNOWN,
+    }
+}
+
+const ICON_CLEAR: [u8; 32] = [
+    0x01, 0x80, 0x01, 0x80, 0x20, 0x04, 0x13, 0xc8, 0x07, 0xe0, 0x0f, 0xf0, 0x0f, 0xf0, 0xcf, 0xf3,
+    0xcf, 0xf3, 0x0f, 0xf0, 0x0f, 0xf0, 0x07, 0xe0, 0x13, 0xc8, 0x20, 0x04, 0x01, 0x80, 0x01, 0x80,
+];
+
+const ICON_CLOUDY: [u8; 32] = [
+    0x00, 0x00, 0x00, 0x00, 0x03, 0xc0, 0x04, 0x20, 0x08, 0x18, 0x38, 0x04, 0x40, 0x02, 0x80, 0x01,
+    0x80, 0x01, 0x80, 0x01, 0x40, 0x02, 0x3f, 0xfc, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+const ICON_RAIN: [u8; 32] = [
+    0x03, 0xc0, 0x04, 0x20, 0x08, 0x18, 0x38, 0x04, 0x40, 0x02, 0x80, 0x01, 0x80, 0x01, 0x80, 0x01,
+    0x40, 0x02, 0x3f, 0xfc, 0x00, 0x00, 0x10, 0x84, 0x21, 0x08, 0x00, 0x00, 0x08, 0x42, 0x10, 0x84,
+];
+
+const ICON_SNOW: [u8; 32] = [
+    0x03, 0xc0, 0x04, 0x20, 0x08, 0x18, 0x38, 0x04, 0x40, 0x02, 0x80, 0x01, 0x80, 0x01, 0x80, 0x01,
+    0x40, 0x02, 0x3f, 0xfc, 0x00, 0x00, 0x21, 0x08, 0x73, 0x9c, 0x21, 0x08, 0x00, 0x00, 0x00, 0x00,
+];
+
+const ICON_THUNDER: [u8; 32] = [
+    0x03, 0xc0, 0x04, 0x20, 0x08, 0x18, 0x38, 0x04, 0x40, 0x02, 0x80, 0x01, 0x80, 0x01, 0x80, 0x01,
+    0x40, 0x02, 0x3f, 0xfc, 0x01, 0x80, 0x03, 0x00, 0x07, 0xe0, 0x01, 0x80, 0x03, 0x00, 0x06, 0x00,
+];
+
+const ICON_FOG: [u8; 32] = [
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x3f, 0xf8, 0x00, 0x00, 0x0f, 0xfe, 0x00, 0x00, 0x7f, 0xf0,
+    0x00, 0x00, 0x1f, 0xfc, 0x00, 0x00, 0x7f, 0xf0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+const ICON_UNKNOWN: [u8; 32] = [
+    0x00, 0x00, 0x00, 0x00, 0x03, 0xc0, 0x06, 0x60, 0x04, 0x30, 0x00, 0x30, 0x00, 0x60, 0x00, 0xc0,
+    0x01, 0x80, 0x01, 0x80, 0x00, 0x00, 0x01, 0x80, 0x01, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
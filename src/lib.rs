@@ -0,0 +1,7891 @@
+//! Fetches indoor readings from InfluxDB and the weather from OpenWeather and
+//! draws them on a Waveshare e-paper panel.
+
+mod icons;
+#[cfg(feature = "qr")]
+mod qr;
+pub mod units;
+
+use embedded_hal::{
+    blocking::{delay::DelayMs, spi::Write},
+    digital::v2::{InputPin, OutputPin},
+};
+
+use linux_embedded_hal::{
+    spidev::{self, SpidevOptions},
+    sysfs_gpio::{self, Direction},
+    Delay, Pin, Spidev,
+};
+
+use embedded_graphics::{
+    fonts::{Font12x16, Font24x32, Font6x12, Font6x8, Font8x16},
+    image::{Image, ImageRaw},
+    pixelcolor::{
+        BinaryColor,
+        BinaryColor::{Off as White, On as Black},
+    },
+    prelude::*,
+    primitives::{Line, Rectangle},
+    style::PrimitiveStyleBuilder,
+};
+
+use epd_waveshare::{
+    epd2in9::{self, EPD2in9},
+    epd2in9bc::{self, EPD2in9bc},
+    epd7in5::{self, EPD7in5},
+    epd7in5_v2,
+    graphics::{Display, DisplayRotation, VarDisplay},
+    prelude::*,
+};
+
+use embedded_text::{
+    alignment::{
+        bottom::BottomAligned, center::CenterAligned, left::LeftAligned, right::RightAligned,
+    },
+    prelude::*,
+};
+
+use std::{
+    cell::{Cell, OnceCell, RefCell},
+    collections::{hash_map::DefaultHasher, BTreeMap},
+    env, error, fmt, fs,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+    rc::Rc,
+    result,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde::{de, Deserialize, Deserializer, Serialize};
+
+use chrono::{
+    format::{Item, StrftimeItems},
+    prelude::*,
+};
+
+use log::{error, info, warn};
+
+/// Everything read from conf.toml, see conf-sample.toml for what each key does
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    // a hash of the keys it was loaded from, so the frame is redrawn after
+    // the config changes even when the readings haven't
+    #[serde(skip)]
+    fingerprint: u64,
+    // only needed when indoor_source is influx, the defaults are a local 1.x
+    #[serde(default = "default_influx_server")]
+    influx_server: String,
+    #[serde(default = "default_influx_database")]
+    influx_database: String,
+    // basic auth for InfluxDB 1.x, or a 2.x API token which wins if both are set
+    influx_user: Option<String>,
+    influx_password: Option<String>,
+    influx_token: Option<String>,
+    // query a 2.x server in Flux, influx_database is then the bucket
+    #[serde(default)]
+    influx_flux: bool,
+    influx_org: Option<String>,
+    // PEM files for an https InfluxDB behind a private CA or wanting a client
+    // certificate
+    influx_ca_file: Option<PathBuf>,
+    influx_client_cert: Option<PathBuf>,
+    influx_client_key: Option<PathBuf>,
+    // rooms to read indoor readings for, the most recently updated one is shown
+    #[serde(default = "default_indoor_sources")]
+    indoor_sources: Vec<IndoorSourceConfig>,
+    // InfluxDB, or with the mqtt feature the broker set up in mqtt
+    #[serde(default)]
+    indoor_source: IndoorSourceKind,
+    #[cfg(feature = "mqtt")]
+    mqtt: Option<MqttConfig>,
+    // hPa the indoor pressure has to move by over 3 hours to be drawn as
+    // rising or falling, 0 turns the trend and its query off
+    #[serde(default = "default_pressure_trend_hpa")]
+    pressure_trend_hpa: f64,
+    // added to every indoor reading to make up for a sensor that reads off, in
+    // celsius, % and hPa whatever the units
+    #[serde(default)]
+    indoor_temp_offset: f64,
+    #[serde(default)]
+    indoor_humidity_offset: f64,
+    #[serde(default)]
+    indoor_pressure_offset: f64,
+    // what the indoor sensors log pressure in, see PressureUnit
+    #[serde(default)]
+    indoor_pressure_unit: PressureUnit,
+    // also read co2 and voc fields for sensors that have them
+    #[serde(default)]
+    indoor_co2: bool,
+    #[serde(default)]
+    indoor_voc: bool,
+    #[serde(deserialize_with = "number_or_string")]
+    lat: f64,
+    #[serde(deserialize_with = "number_or_string")]
+    lon: f64,
+    // where the outdoor readings and forecast come from
+    #[serde(default)]
+    weather_provider: WeatherProvider,
+    // only needed with the openweather provider
+    #[serde(default)]
+    openweather_api_key: String,
+    // shown above the outdoor panels, lat/lon when not set
+    location_name: Option<String>,
+    // places to show the weather for on some days of the week instead of
+    // lat/lon, see Config::location
+    #[serde(default)]
+    locations: Vec<Location>,
+    // strftime format for the time of drawing shown under the forecast
+    #[serde(default = "default_updated_format")]
+    updated_format: String,
+    #[serde(default)]
+    openweather_api_version: OpenWeatherApiVersion,
+    // scheme and host the onecall path is added to, only worth changing to
+    // point at a proxy or a fake server
+    #[serde(default = "default_openweather_base_url")]
+    openweather_base_url: String,
+    // the same for Open-Meteo's forecast api
+    #[serde(default = "default_open_meteo_base_url")]
+    open_meteo_base_url: String,
+    // calls per UTC day to stop at, falling back to the cached response
+    openweather_daily_cap: Option<u32>,
+    // onecall blocks not to fetch, current and daily are the ones we read
+    #[serde(default = "default_openweather_exclude")]
+    openweather_exclude: Vec<String>,
+    // today's high and low from what's left of the hourly block rather than
+    // the whole day, see remaining_day_range
+    #[serde(default)]
+    remaining_day_high_low: bool,
+    #[serde(default)]
+    units: Units,
+    #[serde(default)]
+    outdoor_temp_source: OutdoorTempSource,
+    // how the lines of each forecast day line up
+    #[serde(default)]
+    forecast_alignment: TextAlignment,
+    // which sections go where, see LayoutChoice
+    #[serde(default)]
+    layout: LayoutChoice,
+    // false to not fetch the forecast at all, drawing two_column
+    #[serde(default = "default_show_forecast")]
+    show_forecast: bool,
+    // a link drawn as a QR code in the corner, like a Grafana dashboard
+    #[cfg(feature = "qr")]
+    dashboard_url: Option<String>,
+    // also fetch and show the air quality index, one more request per run
+    #[serde(default)]
+    air_quality: bool,
+    // name today's moon phase in the header, when the source has it
+    #[serde(default)]
+    show_moon_phase: bool,
+    // show the UV index under the outdoor readings
+    #[serde(default)]
+    show_uvi: bool,
+    // show how much warmer it is inside than out under the outdoor temp
+    #[serde(default)]
+    show_temp_delta: bool,
+    // draw white on black
+    #[serde(default)]
+    invert: bool,
+    // lines between the sections of the display, and how thick they are
+    #[serde(default = "default_dividers")]
+    dividers: bool,
+    #[serde(default = "default_divider_width")]
+    divider_width: u32,
+    // a 1px line around the edge of the whole display
+    #[serde(default)]
+    outer_border: bool,
+    // the rain banner and stale marker in the panel's colour, see draw_accents
+    #[serde(default)]
+    color_accents: bool,
+    // the big temperature readings and the smaller ones under them
+    #[serde(default = "default_major_font")]
+    major_font: FontChoice,
+    #[serde(default = "default_minor_font")]
+    minor_font: FontChoice,
+    #[serde(default = "default_retries")]
+    retries: u32,
+    // seconds to wait for a connection and then for each read, so a hung
+    // server can't keep one run going into the next
+    #[serde(default = "default_timeout_connect_secs")]
+    timeout_connect_secs: u64,
+    #[serde(default = "default_timeout_read_secs")]
+    timeout_read_secs: u64,
+    #[serde(default = "default_influx_window")]
+    influx_window: String,
+    #[serde(default = "default_stale_after_minutes")]
+    stale_after_minutes: i64,
+    // today's chance of rain, 0 to 1, over which a banner says so across the
+    // bottom, anything over 1 turns it off
+    #[serde(default = "default_rain_banner_threshold")]
+    rain_banner_threshold: f64,
+    // hours of indoor temperature to graph under the reading, 0 turns it off
+    #[serde(default = "default_history_hours")]
+    history_hours: u32,
+    // how old a cached weather response can be and still stand in for a
+    // failed fetch
+    #[serde(default = "default_weather_max_age_minutes")]
+    weather_max_age_minutes: i64,
+    #[serde(default)]
+    partial_refresh: bool,
+    #[serde(default = "default_full_refresh_every")]
+    full_refresh_every: u32,
+    // minutes between refreshes with --daemon
+    #[serde(default = "default_refresh_interval_minutes")]
+    refresh_interval_minutes: u64,
+    #[serde(default)]
+    change_epsilon: ChangeEpsilon,
+    #[serde(default)]
+    precision: Precision,
+    #[serde(default)]
+    aggregation: Aggregations,
+    #[serde(default)]
+    panel: Panel,
+    // degrees clockwise to turn the picture for how the panel is mounted,
+    // the panel's own landscape orientation when not set
+    rotation: Option<u16>,
+    #[serde(default)]
+    pins: Pins,
+    // lower the clock if long wires or a flaky hat corrupt frames
+    #[serde(default = "default_spi_speed_hz")]
+    spi_speed_hz: u32,
+    #[serde(default)]
+    spi_mode: u8,
+    // seconds the EPD can stay busy before giving up on it, a loose wire or
+    // a hung controller would otherwise keep a run waiting forever
+    #[serde(default = "default_busy_timeout_secs")]
+    busy_timeout_secs: u64,
+    // times to set the EPD up again when it fails, the gpio exports can race
+    // udev handing them over right after boot
+    #[serde(default = "default_epd_init_retries")]
+    epd_init_retries: u32,
+    #[serde(default = "default_quiet_start")]
+    quiet_start: u32,
+    #[serde(default = "default_quiet_end")]
+    quiet_end: u32,
+    #[serde(default)]
+    quiet_mode: QuietMode,
+    // indoor humidity between these (inclusive) is labelled OK
+    #[serde(default = "default_humidity_low")]
+    humidity_low: f64,
+    #[serde(default = "default_humidity_high")]
+    humidity_high: f64,
+    // node_exporter textfile to write the fetched readings to, if any
+    prometheus_path: Option<PathBuf>,
+    // a line of JSON on stdout after each run, see RunSummary
+    #[serde(default)]
+    json_summary: bool,
+    #[cfg(feature = "battery")]
+    #[serde(default = "default_battery_i2c_bus")]
+    battery_i2c_bus: String,
+}
+
+// Somewhere to show the weather for on some weekdays, like a second home on
+// the weekend
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+struct Location {
+    name: Option<String>,
+    #[serde(deserialize_with = "number_or_string")]
+    lat: f64,
+    #[serde(deserialize_with = "number_or_string")]
+    lon: f64,
+    // like ["sat", "sun"]
+    days: Vec<Weekday>,
+}
+
+impl Location {
+    // Almost always lat and lon left unset rather than someone living there,
+    // so it's warned about but still allowed
+    fn at_null_island(&self) -> bool {
+        self.lat == 0.0 && self.lon == 0.0
+    }
+
+    // the name, or its lat/lon without one
+    fn label(&self) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => format!("{:.2}, {:.2}", self.lat, self.lon),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+enum IndoorSourceKind {
+    #[default]
+    Influx,
+    #[cfg(feature = "mqtt")]
+    Mqtt,
+}
+
+// A broker with a sensor's readings on it, one topic each. They're read from
+// the retained messages the broker hands over as soon as we subscribe.
+#[cfg(feature = "mqtt")]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MqttConfig {
+    #[serde(default = "default_mqtt_host")]
+    host: String,
+    #[serde(default = "default_mqtt_port")]
+    port: u16,
+    username: Option<String>,
+    password: Option<String>,
+    #[serde(default = "default_mqtt_client_id")]
+    client_id: String,
+    // drawn like an indoor_sources name
+    #[serde(default = "default_mqtt_name")]
+    name: String,
+    temperature_topic: Option<String>,
+    humidity_topic: Option<String>,
+    pressure_topic: Option<String>,
+}
+
+// An InfluxDB measurement holding temperature, pressure and humidity fields,
+// optionally narrowed down to one room by its tags
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct IndoorSourceConfig {
+    name: String,
+    #[serde(default = "default_measurement")]
+    measurement: String,
+    #[serde(default)]
+    tags: BTreeMap<String, String>,
+}
+
+impl IndoorSourceConfig {
+    // where clauses picking out this source's series, ANDed together with any
+    // others the query needs
+    fn conditions(&self) -> Vec<String> {
+        self.tags
+            .iter()
+            .map(|(tag, value)| format!("{} = {}", quote_ident(tag), quote_string(value)))
+            .collect()
+    }
+}
+
+// InfluxQL identifiers go in double quotes and strings in single ones, both
+// backslash escaped
+fn quote_ident(ident: &str) -> String {
+    format!("\"{}\"", ident.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn quote_string(value: &str) -> String {
+    format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+fn where_clause(conditions: &[String]) -> String {
+    if conditions.is_empty() {
+        String::new()
+    } else {
+        format!(" WHERE {}", conditions.join(" AND "))
+    }
+}
+
+// BCM gpio numbers the EPD is wired to, the defaults match the Waveshare hat
+//
+// | signal | BCM | header pin |
+// |--------|-----|------------|
+// | cs     |   8 |         24 |
+// | busy   |  24 |         18 |
+// | dc     |  25 |         22 |
+// | rst    |  17 |         11 |
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Pins {
+    cs: u64,
+    busy: u64,
+    dc: u64,
+    rst: u64,
+}
+
+impl Default for Pins {
+    fn default() -> Pins {
+        Pins {
+            cs: 8,
+            busy: 24,
+            dc: 25,
+            rst: 17,
+        }
+    }
+}
+
+// What get_epd and selftest read, the keys load_hardware keeps
+const HARDWARE_KEYS: [&str; 7] = [
+    "panel",
+    "rotation",
+    "pins",
+    "spi_speed_hz",
+    "spi_mode",
+    "busy_timeout_secs",
+    "epd_init_retries",
+];
+
+impl Config {
+    /// Reads, parses and validates the config file at `path`. Keys from the
+    /// `secrets_file` it names win over its own, and any top level key can be
+    /// set by an environment variable named after it instead, like
+    /// `MALTER_OPENWEATHER_API_KEY`, which wins over both files.
+    pub fn load(path: &str) -> Result<Config> {
+        Config::load_with(path, env_overrides())
+    }
+
+    // load with the overrides from somewhere other than the environment
+    fn load_with(path: &str, overrides: Vec<(String, toml::Value)>) -> Result<Config> {
+        let conf_file = fs::read_to_string(path).map_err(|e| {
+            Oops::Config(format!(
+                "Could not read {}: {}, try copying conf-sample.toml",
+                path, e
+            ))
+        })?;
+        let env_keys: Vec<&str> = overrides.iter().map(|(key, _)| key.as_str()).collect();
+        let mut table: toml::value::Table =
+            toml::from_str(&conf_file).map_err(|e| config_error(path, e, &env_keys))?;
+        let secrets_file = overrides
+            .iter()
+            .find(|(key, _)| key == "secrets_file")
+            .map(|(_, value)| value)
+            .or_else(|| table.get("secrets_file"));
+        let secrets = match secrets_file {
+            Some(secrets_file) => Some(read_secrets(path, secrets_file)?),
+            None => None,
+        };
+        let source = match &secrets {
+            Some((secrets_path, _)) => format!("{} or {}", path, secrets_path.display()),
+            None => path.to_string(),
+        };
+        let error = |e| config_error(&source, e, &env_keys);
+        let merge = !overrides.is_empty() || secrets.is_some();
+        table.remove("secrets_file");
+        for (key, value) in secrets.into_iter().flat_map(|(_, secrets)| secrets) {
+            table.insert(key, value);
+        }
+        for (key, value) in &overrides {
+            if key == "secrets_file" {
+                continue;
+            }
+            info!("{} set from MALTER_{}", key, key.to_uppercase());
+            table.insert(key.clone(), value.clone());
+        }
+        // the same keys with the same values hash the same, comments and
+        // order aside, and the environment's and secrets' count too
+        let mut hasher = DefaultHasher::new();
+        format!("{:?}", table).hash(&mut hasher);
+        // errors lose their line numbers going through a Value, so only
+        // when there's something to merge in
+        let mut config: Config = if merge {
+            toml::Value::Table(table).try_into().map_err(error)?
+        } else {
+            toml::from_str(&conf_file).map_err(error)?
+        };
+        config.fingerprint = hasher.finish();
+        config.validate()?;
+        if config.home().at_null_island() || config.locations.iter().any(Location::at_null_island) {
+            warn!("lat and lon are both 0, the weather will be for the middle of the Atlantic");
+        }
+        if config.remaining_day_high_low
+            && config.weather_provider == WeatherProvider::OpenWeather
+            && config
+                .openweather_exclude
+                .iter()
+                .any(|block| block == "hourly")
+        {
+            warn!("remaining_day_high_low needs hourly out of openweather_exclude, today will show the whole day's high and low");
+        }
+        Ok(config)
+    }
+
+    /// Only the keys the EPD is set up from in `path`, HARDWARE_KEYS, with
+    /// everything else left at its default, for selftest. Unlike load a
+    /// missing or broken file isn't an error but the default wiring, it's only
+    /// one of those keys having a bad value that is.
+    pub fn load_hardware(path: &str) -> Result<Config> {
+        let table = match fs::read_to_string(path) {
+            Ok(conf_file) => toml::from_str(&conf_file).unwrap_or_else(|e| {
+                warn!("couldn't parse {}, using the default wiring: {}", path, e);
+                toml::value::Table::new()
+            }),
+            Err(e) => {
+                warn!("couldn't read {}, using the default wiring: {}", path, e);
+                toml::value::Table::new()
+            }
+        };
+        let is_hardware = |(key, _): &(String, toml::Value)| HARDWARE_KEYS.contains(&key.as_str());
+        let overrides: Vec<_> = env_overrides().into_iter().filter(is_hardware).collect();
+        let env_keys: Vec<&str> = overrides.iter().map(|(key, _)| key.as_str()).collect();
+        let mut table: toml::value::Table = table.into_iter().filter(is_hardware).collect();
+        for (key, value) in &overrides {
+            info!("{} set from MALTER_{}", key, key.to_uppercase());
+            table.insert(key.clone(), value.clone());
+        }
+        // never used by selftest, only there to get through validate
+        table.insert("lat".to_string(), toml::Value::Float(0.0));
+        table.insert("lon".to_string(), toml::Value::Float(0.0));
+        table.insert(
+            "weather_provider".to_string(),
+            toml::Value::String("open_meteo".to_string()),
+        );
+        let config: Config = toml::Value::Table(table)
+            .try_into()
+            .map_err(|e| config_error(path, e, &env_keys))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    // The first of locations that's shown on `weekday`, or the top level
+    // lat/lon when none are
+    fn location(&self, weekday: Weekday) -> Location {
+        self.locations
+            .iter()
+            .find(|location| location.days.contains(&weekday))
+            .cloned()
+            .unwrap_or_else(|| self.home())
+    }
+
+    // on the days no other location is for
+    fn home(&self) -> Location {
+        Location {
+            name: self.location_name.clone(),
+            lat: self.lat,
+            lon: self.lon,
+            days: Vec::new(),
+        }
+    }
+
+    /// True during the configured quiet hours, when the panel is left alone
+    pub fn is_quiet(&self, hour: u32) -> bool {
+        is_quiet(hour, self.quiet_start, self.quiet_end)
+    }
+
+    /// How long to wait between refreshes when running as a daemon
+    pub fn refresh_interval(&self) -> Duration {
+        Duration::from_secs(self.refresh_interval_minutes * 60)
+    }
+
+    /// Whether to print a RunSummary after each run
+    pub fn json_summary(&self) -> bool {
+        self.json_summary
+    }
+
+    // validate makes sure any configured rotation is one of these
+    fn rotation(&self) -> DisplayRotation {
+        match self.rotation {
+            Some(90) => DisplayRotation::Rotate90,
+            Some(180) => DisplayRotation::Rotate180,
+            Some(270) => DisplayRotation::Rotate270,
+            Some(_) => DisplayRotation::Rotate0,
+            None => self.panel.rotation(),
+        }
+    }
+
+    // Catches values that would otherwise only show up as a confusing http
+    // error or silent zeros later on
+    fn validate(&self) -> Result<()> {
+        fn invalid(field: &str, problem: String) -> Result<()> {
+            Err(Oops::Config(format!("{} {}", field, problem)))
+        }
+
+        if self.indoor_sources.is_empty() {
+            return invalid("indoor_sources", "is empty".to_string());
+        }
+        #[cfg(feature = "mqtt")]
+        if self.indoor_source == IndoorSourceKind::Mqtt {
+            match &self.mqtt {
+                None => return invalid("mqtt", "is needed with indoor_source mqtt".to_string()),
+                Some(mqtt) if mqtt.topics().is_empty() => {
+                    return invalid("mqtt", "has no topics".to_string())
+                }
+                Some(_) => {}
+            }
+        }
+        let openweather = self.weather_provider == WeatherProvider::OpenWeather;
+        if openweather && self.openweather_api_key.trim().is_empty() {
+            return invalid("openweather_api_key", "is empty".to_string());
+        }
+        if !openweather && self.air_quality {
+            return invalid(
+                "air_quality",
+                "is only available from the openweather provider".to_string(),
+            );
+        }
+        for (field, degrees, limit) in [("lat", self.lat, 90.0), ("lon", self.lon, 180.0)] {
+            if !(-limit..=limit).contains(&degrees) {
+                return invalid(
+                    field,
+                    format!("{} is outside -{}..{}", degrees, limit, limit),
+                );
+            }
+        }
+        for (i, location) in self.locations.iter().enumerate() {
+            let field = |name: &str| format!("locations[{}].{}", i, name);
+            for (name, degrees, limit) in
+                [("lat", location.lat, 90.0), ("lon", location.lon, 180.0)]
+            {
+                if !(-limit..=limit).contains(&degrees) {
+                    return invalid(
+                        &field(name),
+                        format!("{} is outside -{}..{}", degrees, limit, limit),
+                    );
+                }
+            }
+            if location.days.is_empty() {
+                return invalid(&field("days"), "is empty".to_string());
+            }
+        }
+        match url::Url::parse(&self.influx_server) {
+            Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {}
+            Ok(_) => {
+                return invalid(
+                    "influx_server",
+                    format!("{:?} is not an http(s) url", self.influx_server),
+                )
+            }
+            Err(e) => {
+                return invalid(
+                    "influx_server",
+                    format!("{:?} is not a valid url: {}", self.influx_server, e),
+                )
+            }
+        }
+        if self.influx_client_cert.is_some() != self.influx_client_key.is_some() {
+            return invalid(
+                "influx_client_cert",
+                "and influx_client_key have to be set together".to_string(),
+            );
+        }
+        if self.influx_flux && self.influx_org.is_none() {
+            return invalid("influx_org", "is needed with influx_flux".to_string());
+        }
+        // chrono only finds a bad format when it's displayed, and panics then
+        if StrftimeItems::new(&self.updated_format).any(|item| item == Item::Error) {
+            return invalid(
+                "updated_format",
+                format!("{:?} is not a valid strftime format", self.updated_format),
+            );
+        }
+        // the base urls have to be http(s) like the influx server
+        for (field, base_url) in [
+            ("openweather_base_url", &self.openweather_base_url),
+            ("open_meteo_base_url", &self.open_meteo_base_url),
+        ] {
+            match url::Url::parse(base_url) {
+                Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {}
+                _ => return invalid(field, format!("{:?} is not an http(s) url", base_url)),
+            }
+        }
+        for block in &self.openweather_exclude {
+            match block.as_str() {
+                "minutely" | "hourly" | "alerts" => {}
+                "current" | "daily" => {
+                    return invalid(
+                        "openweather_exclude",
+                        format!("can't have {:?}, it's what the display shows", block),
+                    )
+                }
+                _ => {
+                    return invalid(
+                        "openweather_exclude",
+                        format!("{:?} is not a onecall block", block),
+                    )
+                }
+            }
+        }
+        // interpolated straight into the query so make sure it's really a duration
+        if !is_influx_duration(&self.influx_window) {
+            return invalid(
+                "influx_window",
+                format!(
+                    "{:?} is not a valid InfluxDB duration (like 15m or 1h30m)",
+                    self.influx_window
+                ),
+            );
+        }
+        for (field, hour) in [
+            ("quiet_start", self.quiet_start),
+            ("quiet_end", self.quiet_end),
+        ] {
+            if hour > 23 {
+                return invalid(field, format!("{} is not an hour (0-23)", hour));
+            }
+        }
+        if self.refresh_interval_minutes == 0 {
+            return invalid("refresh_interval_minutes", "is 0".to_string());
+        }
+        // thicker than that and the dividers start eating into the text
+        if !(1..=MAX_DIVIDER_WIDTH).contains(&self.divider_width) {
+            return invalid(
+                "divider_width",
+                format!("{} is outside 1..{}", self.divider_width, MAX_DIVIDER_WIDTH),
+            );
+        }
+        if let Some(rotation) = self.rotation {
+            if ![0, 90, 180, 270].contains(&rotation) {
+                return invalid(
+                    "rotation",
+                    format!("{} is not one of 0, 90, 180 or 270", rotation),
+                );
+            }
+        }
+        if self.color_accents && !self.panel.has_color() {
+            return invalid(
+                "color_accents",
+                "needs a panel with a colour, like 2in9bc".to_string(),
+            );
+        }
+        if self.spi_mode > 3 {
+            return invalid(
+                "spi_mode",
+                format!("{} is not an SPI mode (0-3)", self.spi_mode),
+            );
+        }
+        #[cfg(feature = "qr")]
+        if let Some(url) = &self.dashboard_url {
+            if url.len() > qr::MAX_BYTES {
+                return invalid(
+                    "dashboard_url",
+                    format!(
+                        "is {} bytes, over the {} a QR code here can hold",
+                        url.len(),
+                        qr::MAX_BYTES
+                    ),
+                );
+            }
+        }
+        if self.busy_timeout_secs == 0 {
+            return invalid("busy_timeout_secs", "is 0".to_string());
+        }
+        if self.humidity_low > self.humidity_high {
+            return invalid(
+                "humidity_low",
+                format!("{} is above humidity_high", self.humidity_low),
+            );
+        }
+        if self.pressure_trend_hpa < 0.0 {
+            return invalid(
+                "pressure_trend_hpa",
+                format!("{} is negative", self.pressure_trend_hpa),
+            );
+        }
+        Ok(())
+    }
+}
+
+// How much a reading has to move since it was last drawn before it's worth
+// refreshing the panel for. It's measured from what was drawn, which is only
+// saved after a refresh, so a reading creeping along a little each run still
+// gets drawn once it's crept far enough.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ChangeEpsilon {
+    temp: f64,
+    humidity: f64,
+    pressure: f64,
+    pop: f64,
+    wind_speed: f64,
+    wind_deg: f64,
+    co2: f64,
+    voc: f64,
+}
+
+impl Default for ChangeEpsilon {
+    fn default() -> ChangeEpsilon {
+        ChangeEpsilon {
+            temp: 0.05,
+            humidity: 0.05,
+            pressure: 0.5,
+            pop: 0.005,
+            wind_speed: 0.1,
+            wind_deg: 10.0,
+            co2: 10.0,
+            voc: 10.0,
+        }
+    }
+}
+
+// Decimal places each reading is drawn with, fewer means fewer refreshes for
+// a trailing digit flickering back and forth
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Precision {
+    temp: usize,
+    humidity: usize,
+    // the unit's own default when not set, see Units::format_pressure
+    pressure: Option<usize>,
+}
+
+impl Default for Precision {
+    fn default() -> Precision {
+        Precision {
+            temp: 1,
+            humidity: 1,
+            pressure: None,
+        }
+    }
+}
+
+// How the readings in each influx_window are combined into the one shown
+#[derive(Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+enum Aggregation {
+    #[default]
+    Mean,
+    Last,
+    Median,
+    Max,
+    Min,
+}
+
+impl Aggregation {
+    // the Flux function, InfluxQL's is the same name in capitals
+    fn function(self) -> &'static str {
+        match self {
+            Aggregation::Mean => "mean",
+            Aggregation::Last => "last",
+            Aggregation::Median => "median",
+            Aggregation::Max => "max",
+            Aggregation::Min => "min",
+        }
+    }
+}
+
+// The aggregation for each indoor field, all means unless set
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct Aggregations {
+    temperature: Aggregation,
+    pressure: Aggregation,
+    humidity: Aggregation,
+    co2: Aggregation,
+    voc: Aggregation,
+}
+
+impl Aggregations {
+    // by InfluxDB field name, the ones from indoor_fields
+    fn of(&self, field: &str) -> Aggregation {
+        match field {
+            "temperature" => self.temperature,
+            "pressure" => self.pressure,
+            "humidity" => self.humidity,
+            "co2" => self.co2,
+            "voc" => self.voc,
+            _ => Aggregation::Mean,
+        }
+    }
+}
+
+// serde's unknown field error lists every key there could be, too many to
+// read for Config, so name just the one that's wrong
+fn config_error(path: &str, e: toml::de::Error, env_keys: &[&str]) -> Oops {
+    let msg = e.to_string();
+    let key = msg
+        .strip_prefix("unknown field `")
+        .and_then(|rest| rest.split_once('`'));
+    match key {
+        Some((key, _)) if env_keys.contains(&key) => Oops::Config(format!(
+            "MALTER_{} is set but {:?} isn't a config key",
+            key.to_uppercase(),
+            key
+        )),
+        Some((key, _)) => Oops::Config(format!(
+            "{} has an unknown key {:?}, check it for typos",
+            path, key
+        )),
+        None => e.into(),
+    }
+}
+
+// The secrets_file's path and keys, for things like openweather_api_key kept
+// out of a config that's checked in. A relative path is from the config's
+// directory. Only top level keys are merged, a table replaces the config's.
+fn read_secrets(path: &str, secrets_file: &toml::Value) -> Result<(PathBuf, toml::value::Table)> {
+    let secrets_file = secrets_file
+        .as_str()
+        .ok_or_else(|| Oops::Config("secrets_file should be a path".to_string()))?;
+    let secrets_path = Path::new(path)
+        .parent()
+        .unwrap_or_else(|| Path::new(""))
+        .join(secrets_file);
+    let invalid = |e: &dyn fmt::Display| {
+        Oops::Config(format!("Could not read {}: {}", secrets_path.display(), e))
+    };
+    let secrets = fs::read_to_string(&secrets_path).map_err(|e| invalid(&e))?;
+    let secrets = toml::from_str(&secrets).map_err(|e| invalid(&e))?;
+    Ok((secrets_path, secrets))
+}
+
+// Top level keys that are strings in Config, or enums named by one, which an
+// environment variable sets as is even when it looks like a number, like an
+// all digit password or openweather_api_version 2.5
+const STRING_KEYS: [&str; 33] = [
+    "influx_server",
+    "influx_database",
+    "influx_user",
+    "influx_password",
+    "influx_token",
+    "influx_org",
+    "influx_ca_file",
+    "influx_client_cert",
+    "influx_client_key",
+    "indoor_source",
+    "indoor_pressure_unit",
+    "weather_provider",
+    "openweather_api_key",
+    "location_name",
+    "updated_format",
+    "openweather_api_version",
+    "openweather_base_url",
+    "open_meteo_base_url",
+    "units",
+    "outdoor_temp_source",
+    "forecast_alignment",
+    "layout",
+    "dashboard_url",
+    "major_font",
+    "minor_font",
+    "influx_window",
+    "panel",
+    "quiet_mode",
+    "prometheus_path",
+    "battery_i2c_bus",
+    "secrets_file",
+    "lat",
+    "lon",
+];
+
+// MALTER_* environment variables as top level keys
+fn env_overrides() -> Vec<(String, toml::Value)> {
+    env::vars_os()
+        .filter_map(|(name, value)| {
+            let key = name.to_str()?.strip_prefix("MALTER_")?.to_lowercase();
+            let value = env_value(&key, value.into_string().ok()?);
+            Some((key, value))
+        })
+        .collect()
+}
+
+// Values for the STRING_KEYS are strings, the rest are read as TOML when they
+// parse as it, so MALTER_RETRIES=5 is a number, and are strings otherwise
+fn env_value(key: &str, value: String) -> toml::Value {
+    if STRING_KEYS.contains(&key) {
+        return toml::Value::String(value);
+    }
+    toml::from_str::<toml::value::Table>(&format!("value = {}", value))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or(toml::Value::String(value))
+}
+
+// older configs have lat/lon quoted, so take either a toml number or a string
+// holding one
+fn number_or_string<'de, D: Deserializer<'de>>(deserializer: D) -> result::Result<f64, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum NumberOrString {
+        Number(f64),
+        String(String),
+    }
+
+    match NumberOrString::deserialize(deserializer)? {
+        NumberOrString::Number(n) => Ok(n),
+        NumberOrString::String(s) => s
+            .trim()
+            .parse()
+            .map_err(|_| de::Error::custom(format!("{:?} is not a number", s))),
+    }
+}
+
+fn default_influx_server() -> String {
+    "http://localhost:8086/query".to_string()
+}
+
+fn default_influx_database() -> String {
+    "default".to_string()
+}
+
+#[cfg(feature = "mqtt")]
+fn default_mqtt_host() -> String {
+    "localhost".to_string()
+}
+
+#[cfg(feature = "mqtt")]
+fn default_mqtt_port() -> u16 {
+    1883
+}
+
+#[cfg(feature = "mqtt")]
+fn default_mqtt_client_id() -> String {
+    "malter".to_string()
+}
+
+#[cfg(feature = "mqtt")]
+fn default_mqtt_name() -> String {
+    "indoor".to_string()
+}
+
+fn default_indoor_sources() -> Vec<IndoorSourceConfig> {
+    vec![IndoorSourceConfig {
+        name: "indoor".to_string(),
+        measurement: default_measurement(),
+        tags: BTreeMap::new(),
+    }]
+}
+
+fn default_pressure_trend_hpa() -> f64 {
+    1.0
+}
+
+fn default_measurement() -> String {
+    "indoor".to_string()
+}
+
+fn default_updated_format() -> String {
+    "%H:%M".to_string()
+}
+
+fn default_openweather_base_url() -> String {
+    "https://api.openweathermap.org".to_string()
+}
+
+fn default_open_meteo_base_url() -> String {
+    "https://api.open-meteo.com".to_string()
+}
+
+fn default_openweather_exclude() -> Vec<String> {
+    ["minutely", "hourly", "alerts"].map(String::from).to_vec()
+}
+
+fn default_major_font() -> FontChoice {
+    FontChoice::Font12x16
+}
+
+fn default_minor_font() -> FontChoice {
+    FontChoice::Font8x16
+}
+
+fn default_dividers() -> bool {
+    true
+}
+
+fn default_show_forecast() -> bool {
+    true
+}
+
+const MAX_DIVIDER_WIDTH: u32 = 3;
+
+fn default_divider_width() -> u32 {
+    1
+}
+
+fn default_retries() -> u32 {
+    3
+}
+
+fn default_timeout_connect_secs() -> u64 {
+    5
+}
+
+fn default_timeout_read_secs() -> u64 {
+    10
+}
+
+fn default_influx_window() -> String {
+    "15m".to_string()
+}
+
+fn default_stale_after_minutes() -> i64 {
+    30
+}
+
+fn default_rain_banner_threshold() -> f64 {
+    0.6
+}
+
+fn default_history_hours() -> u32 {
+    24
+}
+
+fn default_weather_max_age_minutes() -> i64 {
+    120
+}
+
+fn default_full_refresh_every() -> u32 {
+    10
+}
+
+fn default_refresh_interval_minutes() -> u64 {
+    15
+}
+
+fn default_spi_speed_hz() -> u32 {
+    4_000_000
+}
+
+// a full refresh of the 7in5 takes the longest, under 10 seconds
+fn default_busy_timeout_secs() -> u64 {
+    30
+}
+
+fn default_epd_init_retries() -> u32 {
+    3
+}
+
+fn default_quiet_start() -> u32 {
+    23
+}
+
+fn default_quiet_end() -> u32 {
+    7
+}
+
+fn default_humidity_low() -> f64 {
+    30.0
+}
+
+fn default_humidity_high() -> f64 {
+    50.0
+}
+
+#[cfg(feature = "battery")]
+fn default_battery_i2c_bus() -> String {
+    "/dev/i2c-1".to_string()
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+enum Units {
+    #[default]
+    Metric,
+    Imperial,
+}
+
+impl Units {
+    // value for the openweather `units` query parameter
+    fn api_name(self) -> &'static str {
+        match self {
+            Units::Metric => "metric",
+            Units::Imperial => "imperial",
+        }
+    }
+
+    // Open-Meteo's temperature_unit and wind_speed_unit, picked to match what
+    // OpenWeather sends for api_name
+    fn open_meteo_units(self) -> (&'static str, &'static str) {
+        match self {
+            Units::Metric => ("celsius", "ms"),
+            Units::Imperial => ("fahrenheit", "mph"),
+        }
+    }
+
+    // a difference between two temperatures, always with its sign, which is +
+    // rather than - for one that rounds to nothing
+    fn format_temp_delta(self, delta: f64, precision: usize) -> String {
+        let delta = if (delta * 10f64.powi(precision as i32)).round() == 0.0 {
+            0.0
+        } else {
+            delta
+        };
+        match self {
+            Units::Metric => format!("{:+.*}C", precision, delta),
+            Units::Imperial => format!("{:+.*}F", precision, delta),
+        }
+    }
+
+    fn format_temp(self, temp: f64, precision: usize) -> String {
+        match self {
+            Units::Metric => format!("{:.*}C", precision, temp),
+            Units::Imperial => format!("{:.*}F", precision, temp),
+        }
+    }
+
+    // whole hPa, or hundredths of an inHg, unless a precision is given
+    fn format_pressure(self, pressure: f64, precision: Option<usize>) -> String {
+        match self {
+            Units::Metric => format!("{:.*} hPa", precision.unwrap_or(0), pressure),
+            Units::Imperial => format!("{:.*} inHg", precision.unwrap_or(2), pressure),
+        }
+    }
+
+    // openweather gives m/s for metric, km/h is friendlier
+    fn format_wind(self, speed: f64) -> String {
+        match self {
+            Units::Metric => format!("{:.0}km/h", units::ms_to_kmh(speed)),
+            Units::Imperial => format!("{:.0}mph", speed),
+        }
+    }
+
+    // unit suffixes for the prometheus metric names, temperature then pressure
+    fn prometheus_units(self) -> (&'static str, &'static str) {
+        match self {
+            Units::Metric => ("celsius", "hpa"),
+            Units::Imperial => ("fahrenheit", "inhg"),
+        }
+    }
+
+    // influx always stores celsius, openweather converts for us
+    fn convert_temp(self, celsius: f64) -> f64 {
+        match self {
+            Units::Metric => celsius,
+            Units::Imperial => units::c_to_f(celsius),
+        }
+    }
+
+    // influx and openweather always report pressure in hPa
+    fn convert_pressure(self, hpa: f64) -> f64 {
+        match self {
+            Units::Metric => hpa,
+            Units::Imperial => units::hpa_to_inhg(hpa),
+        }
+    }
+}
+
+// The three column indoor, outdoor and forecast layout, or two columns of just
+// indoor and outdoor with bigger readings
+#[derive(Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+enum LayoutChoice {
+    #[default]
+    ThreeColumn,
+    TwoColumn,
+}
+
+// What's left on the panel through quiet hours: whatever was last drawn, a
+// blank panel, or just the time
+#[derive(Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+enum QuietMode {
+    #[default]
+    Skip,
+    Clear,
+    Clock,
+}
+
+// Some BMP280 drivers log pressure in Pa rather than hPa. Auto tells them
+// apart by size, as nowhere near the ground sees more than 2000 hPa or less
+// than 2000 Pa.
+#[derive(Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+enum PressureUnit {
+    #[default]
+    Auto,
+    Hpa,
+    Pa,
+}
+
+impl PressureUnit {
+    fn to_hpa(self, pressure: f64) -> f64 {
+        match self {
+            PressureUnit::Auto if pressure > 2000.0 => pressure / 100.0,
+            PressureUnit::Auto | PressureUnit::Hpa => pressure,
+            PressureUnit::Pa => pressure / 100.0,
+        }
+    }
+}
+
+// Where the outdoor readings and forecast come from. Open-Meteo doesn't need a
+// key but has no air quality or moon phase in its forecast.
+#[derive(Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+enum WeatherProvider {
+    #[default]
+    #[serde(rename = "openweather")]
+    OpenWeather,
+    OpenMeteo,
+}
+
+// Which version of the onecall api to use. 2.5 is deprecated and rejects new
+// keys with a 401, but older keys may only work with it. The fields read here
+// (current, daily temp/pop/weather/wind) are the same in both.
+#[derive(Deserialize, Clone, Copy, PartialEq, Default)]
+enum OpenWeatherApiVersion {
+    #[serde(rename = "2.5")]
+    V2_5,
+    #[default]
+    #[serde(rename = "3.0")]
+    V3_0,
+}
+
+impl OpenWeatherApiVersion {
+    fn onecall_url(self, base_url: &str) -> String {
+        let version = match self {
+            OpenWeatherApiVersion::V2_5 => "2.5",
+            OpenWeatherApiVersion::V3_0 => "3.0",
+        };
+        format!(
+            "{}/data/{}/onecall",
+            base_url.trim_end_matches('/'),
+            version
+        )
+    }
+}
+
+// The Waveshare panels that can be driven, by the size in inches. 2in9 is the
+// original and the only one with a quick refresh, 2in9bc the same size with
+// red (or yellow) too.
+#[derive(Deserialize, Clone, Copy, PartialEq, Default)]
+enum Panel {
+    #[default]
+    #[serde(rename = "2in9")]
+    Epd2in9,
+    #[serde(rename = "2in9bc")]
+    Epd2in9bc,
+    #[serde(rename = "7in5")]
+    Epd7in5,
+    #[serde(rename = "7in5_v2")]
+    Epd7in5V2,
+}
+
+impl Panel {
+    // in the controller's own orientation, before rotation
+    fn size(self) -> (u32, u32) {
+        match self {
+            Panel::Epd2in9 => (epd2in9::WIDTH, epd2in9::HEIGHT),
+            Panel::Epd2in9bc => (epd2in9bc::WIDTH, epd2in9bc::HEIGHT),
+            Panel::Epd7in5 => (epd7in5::WIDTH, epd7in5::HEIGHT),
+            Panel::Epd7in5V2 => (epd7in5_v2::WIDTH, epd7in5_v2::HEIGHT),
+        }
+    }
+
+    // the layout wants landscape, the 2in9 is portrait natively
+    fn rotation(self) -> DisplayRotation {
+        match self {
+            Panel::Epd2in9 | Panel::Epd2in9bc => DisplayRotation::Rotate90,
+            Panel::Epd7in5 | Panel::Epd7in5V2 => DisplayRotation::Rotate0,
+        }
+    }
+
+    fn supports_quick_refresh(self) -> bool {
+        self == Panel::Epd2in9
+    }
+
+    // a third colour for color_accents
+    fn has_color(self) -> bool {
+        self == Panel::Epd2in9bc
+    }
+}
+
+// The embedded-graphics fonts that can be picked in the config, by size
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+enum FontChoice {
+    #[serde(rename = "6x8")]
+    Font6x8,
+    #[serde(rename = "6x12")]
+    Font6x12,
+    #[serde(rename = "8x16")]
+    Font8x16,
+    #[serde(rename = "12x16")]
+    Font12x16,
+    #[serde(rename = "24x32")]
+    Font24x32,
+}
+
+impl FontChoice {
+    // smallest first
+    const ALL: [FontChoice; 5] = [
+        FontChoice::Font6x8,
+        FontChoice::Font6x12,
+        FontChoice::Font8x16,
+        FontChoice::Font12x16,
+        FontChoice::Font24x32,
+    ];
+
+    // the next size up, or this if it's already the biggest
+    fn larger(self) -> FontChoice {
+        FontChoice::ALL
+            .into_iter()
+            .find(|font| font.character_size().width > self.character_size().width)
+            .unwrap_or(self)
+    }
+
+    // the fonts are monospaced without any spacing between characters
+    fn character_size(self) -> Size {
+        match self {
+            FontChoice::Font6x8 => Font6x8::CHARACTER_SIZE,
+            FontChoice::Font6x12 => Font6x12::CHARACTER_SIZE,
+            FontChoice::Font8x16 => Font8x16::CHARACTER_SIZE,
+            FontChoice::Font12x16 => Font12x16::CHARACTER_SIZE,
+            FontChoice::Font24x32 => Font24x32::CHARACTER_SIZE,
+        }
+    }
+
+    // how much room text takes up, one line per \n
+    fn text_size(self, text: &str) -> Size {
+        let character = self.character_size();
+        let columns = text.lines().map(|line| line.chars().count()).max();
+        let rows = text.lines().count();
+        Size::new(
+            character.width * columns.unwrap_or(0) as u32,
+            character.height * rows as u32,
+        )
+    }
+
+    // This font if text fits in bounds, otherwise the biggest smaller one it
+    // does fit in, or the smallest if nothing does. Long readings like -12.3C
+    // or 1013 hPa get clipped or wrapped otherwise.
+    fn fitting(self, text: &str, bounds: Size) -> FontChoice {
+        let fits = |font: &FontChoice| {
+            let size = font.text_size(text);
+            size.width <= bounds.width && size.height <= bounds.height
+        };
+        FontChoice::ALL
+            .into_iter()
+            .filter(|font| font.character_size().width <= self.character_size().width)
+            .filter(|font| font.character_size().height <= self.character_size().height)
+            .rev()
+            .find(fits)
+            .unwrap_or(FontChoice::Font6x8)
+    }
+}
+
+// Horizontal alignment for a panel's text, the panels all default to centered
+#[derive(Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+enum TextAlignment {
+    Left,
+    #[default]
+    Center,
+    Right,
+}
+
+// Which openweather reading to show as the outdoor temperature, feels_like
+// (the default) is drawn with a ~ in front since it's a perceived temperature
+#[derive(Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+enum OutdoorTempSource {
+    #[default]
+    FeelsLike,
+    Temp,
+}
+
+impl OutdoorTempSource {
+    // field in openweather's `current` block
+    fn field(self) -> &'static str {
+        match self {
+            OutdoorTempSource::FeelsLike => "feels_like",
+            OutdoorTempSource::Temp => "temp",
+        }
+    }
+}
+
+/// Readings are None when the source didn't report them, as opposed to a real 0
+#[derive(Serialize, Deserialize)]
+pub struct IndoorData {
+    /// name of the indoor source (room) this came from
+    #[serde(default)]
+    pub label: String,
+    /// start of the newest group by time() bucket
+    pub sampled_at: Option<DateTime<Utc>>,
+    pub temp: Option<f64>,
+    pub humidity: Option<f64>,
+    pub pressure: Option<f64>,
+    /// worked out from temp and humidity, same units as temp
+    pub dewpoint: Option<f64>,
+    /// ppm, only read with indoor_co2 set
+    pub co2: Option<f64>,
+    /// in whatever the sensor reports, only read with indoor_voc set
+    pub voc: Option<f64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OutdoorData {
+    pub temp: Option<f64>,
+    pub humidity: Option<f64>,
+    pub pressure: Option<f64>,
+    /// today's, in the forecast location's timezone
+    pub sunrise: Option<DateTime<FixedOffset>>,
+    pub sunset: Option<DateTime<FixedOffset>>,
+    /// OpenWeather's air quality index, 1 (good) to 5 (very poor)
+    pub aqi: Option<u8>,
+    /// the UV index right now
+    #[serde(default)]
+    pub uvi: Option<f64>,
+}
+
+/// Up to three days starting with today, there can be fewer if that's all
+/// the source had
+#[derive(Serialize, Deserialize)]
+pub struct ForecastData {
+    pub days: Vec<DayForecast>,
+    /// Today's moon phase, 0 and 1 are new moon and 0.5 is full
+    #[serde(default)]
+    pub moon_phase: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DayForecast {
+    /// in the forecast location's timezone
+    pub date: Option<NaiveDate>,
+    pub high: f64,
+    pub low: f64,
+    /// one word summary like Rain or Clouds
+    pub condition: String,
+    /// openweather's condition code, picks the icon
+    #[serde(default)]
+    pub condition_id: Option<u32>,
+    pub pop: f64,
+    /// in whatever units openweather was asked for, m/s or mph
+    pub wind_speed: f64,
+    pub wind_deg: f64,
+}
+
+/// Whatever we managed to fetch, a source that failed is left as None
+#[derive(Serialize, Deserialize)]
+pub struct Data {
+    /// one per room that answered
+    pub indoor: Vec<IndoorData>,
+    pub outdoor: Option<OutdoorData>,
+    pub forecast: Option<ForecastData>,
+    pub battery: Option<BatteryData>,
+    /// oldest first, one mean per influx_window, empty if it couldn't be fetched
+    #[serde(default)]
+    pub indoor_history: Vec<f64>,
+    /// which way the indoor pressure went over the last few hours
+    #[serde(default)]
+    pub pressure_trend: Option<Trend>,
+}
+
+/// A reading going up, down or staying about the same
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Trend {
+    Rising,
+    Falling,
+    Steady,
+}
+
+impl Trend {
+    // drawn after the reading, the fonts don't have arrows
+    fn glyph(self) -> &'static str {
+        match self {
+            Trend::Rising => "^",
+            Trend::Falling => "v",
+            Trend::Steady => "-",
+        }
+    }
+}
+
+impl Data {
+    /// The room with the most recent sample, which is the one that gets drawn
+    pub fn newest_indoor(&self) -> Option<&IndoorData> {
+        self.indoor.iter().max_by_key(|indoor| indoor.sampled_at)
+    }
+}
+
+/// From a MAX17048 fuel gauge, only read with the battery feature
+#[derive(Serialize, Deserialize)]
+pub struct BatteryData {
+    pub voltage: f64,
+    pub percent: f64,
+}
+
+// What was on the panel after the last refresh, kept to skip redrawing
+// when nothing has changed
+#[derive(Serialize, Deserialize)]
+struct LastDrawn {
+    data: Data,
+    indoor_stale: bool,
+    #[serde(default)]
+    rain_banner: bool,
+    #[serde(default)]
+    text: DrawnText,
+}
+
+impl LastDrawn {
+    fn new(config: &Config, data: Data, now: DateTime<Local>) -> LastDrawn {
+        LastDrawn {
+            indoor_stale: indoor_is_stale(&data, config, now.with_timezone(&Utc)),
+            rain_banner: rain_expected(&data, config),
+            text: DrawnText {
+                header: header_label(config, &data, now),
+                sun: data.outdoor.as_ref().and_then(|d| sun_text(d, now)),
+                footer: updated_text(config, now),
+                config: config.fingerprint,
+            },
+            data,
+        }
+    }
+}
+
+// What's drawn from the time and the config rather than straight from the
+// readings, an older cache without it is always a change. The footer's in
+// it so it says when the last run was, not when the readings last moved.
+#[derive(Serialize, Deserialize, Default, PartialEq)]
+struct DrawnText {
+    header: String,
+    sun: Option<String>,
+    footer: String,
+    config: u64,
+}
+
+/// Everything that can go wrong in a run
+#[derive(Debug)]
+pub enum Oops {
+    Io(std::io::Error),
+    // boxed, ureq's error is big enough to bloat every Result
+    Http(Box<ureq::Error>),
+    /// a server answered with an error status, like a 401 for a bad api key
+    Status {
+        service: String,
+        code: u16,
+        reason: String,
+    },
+    Toml(toml::de::Error),
+    Json(serde_json::Error),
+    /// bad arguments, a missing config file or a bad value in it
+    Config(String),
+    /// a request took longer than the configured timeouts
+    Timeout(String),
+    /// the TLS handshake failed, like for a server certificate we don't trust
+    Tls(String),
+    /// a source answered but without anything we could use
+    Data(String),
+    /// talking to the EPD failed
+    Display(String),
+}
+
+impl Oops {
+    /// The process exit code for a run that failed with this, so whatever
+    /// runs malter can tell what kind of failure it was: 2 for the config or
+    /// arguments, 3 for a source that couldn't be reached or didn't give us
+    /// anything usable, 4 for the EPD and 1 for anything else
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Oops::Config(_) | Oops::Toml(_) => 2,
+            Oops::Http(_)
+            | Oops::Status { .. }
+            | Oops::Json(_)
+            | Oops::Timeout(_)
+            | Oops::Tls(_)
+            | Oops::Data(_) => 3,
+            Oops::Display(_) => 4,
+            Oops::Io(_) => 1,
+        }
+    }
+
+    // spidev and gpio errors are plain io errors, this keeps them apart from
+    // file and network ones
+    fn display(e: impl fmt::Display) -> Oops {
+        Oops::Display(e.to_string())
+    }
+}
+
+impl From<std::io::Error> for Oops {
+    fn from(e: std::io::Error) -> Oops {
+        Oops::Io(e)
+    }
+}
+
+impl From<ureq::Error> for Oops {
+    fn from(e: ureq::Error) -> Oops {
+        if is_timeout(&e) {
+            Oops::Timeout(e.to_string())
+        } else if let Some(tls) = tls_error(&e) {
+            Oops::Tls(tls.to_string())
+        } else {
+            Oops::Http(Box::new(e))
+        }
+    }
+}
+
+impl From<serde_json::Error> for Oops {
+    fn from(e: serde_json::Error) -> Oops {
+        Oops::Json(e)
+    }
+}
+
+// the display buffers can't actually fail to draw, but this lets draw use ?
+impl From<std::convert::Infallible> for Oops {
+    fn from(e: std::convert::Infallible) -> Oops {
+        match e {}
+    }
+}
+
+impl From<toml::de::Error> for Oops {
+    fn from(e: toml::de::Error) -> Oops {
+        Oops::Toml(e)
+    }
+}
+
+impl error::Error for Oops {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Oops::Io(e) => Some(e),
+            Oops::Http(e) => Some(e.as_ref()),
+            Oops::Toml(e) => Some(e),
+            Oops::Json(e) => Some(e),
+            Oops::Status { .. }
+            | Oops::Config(_)
+            | Oops::Timeout(_)
+            | Oops::Tls(_)
+            | Oops::Data(_)
+            | Oops::Display(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Oops {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Oops::Io(e) => e.fmt(f),
+            Oops::Http(e) => e.fmt(f),
+            Oops::Status {
+                service,
+                code,
+                reason,
+            } => write!(f, "{} request failed: {} {}", service, code, reason),
+            Oops::Toml(e) => write!(f, "invalid config: {}", e),
+            Oops::Json(e) => write!(f, "invalid json: {}", e),
+            Oops::Config(msg) | Oops::Data(msg) => msg.fmt(f),
+            Oops::Timeout(msg) => write!(f, "timed out: {}", msg),
+            Oops::Tls(msg) => write!(f, "TLS handshake failed: {}", msg),
+            Oops::Display(msg) => write!(f, "display error: {}", msg),
+        }
+    }
+}
+
+pub type Result<T> = result::Result<T, Oops>;
+
+// The driver for whichever panel is configured. The drivers share a trait but
+// it can't be made into a trait object, so this forwards the few calls we use.
+// Generic over the bus and pins like the drivers are, get_epd hands out the
+// real spidev and sysfs ones but anything embedded-hal will do.
+enum Epd<SPI, CS, BUSY, DC, RST> {
+    E2in9(EPD2in9<SPI, CS, BUSY, DC, RST>),
+    E2in9bc(EPD2in9bc<SPI, CS, BUSY, DC, RST>),
+    E7in5(EPD7in5<SPI, CS, BUSY, DC, RST>),
+    E7in5V2(epd7in5_v2::EPD7in5<SPI, CS, BUSY, DC, RST>),
+}
+
+impl<SPI, CS, BUSY, DC, RST> Epd<SPI, CS, BUSY, DC, RST>
+where
+    SPI: Write<u8>,
+    SPI::Error: fmt::Display,
+    CS: OutputPin,
+    BUSY: InputPin,
+    DC: OutputPin,
+    RST: OutputPin,
+{
+    // sets up and resets the driver for `panel`
+    fn new(
+        panel: Panel,
+        spi: &mut SPI,
+        cs: CS,
+        busy: BUSY,
+        dc: DC,
+        rst: RST,
+        delay: &mut impl DelayMs<u8>,
+    ) -> Result<Self> {
+        match panel {
+            Panel::Epd2in9 => EPD2in9::new(spi, cs, busy, dc, rst, delay).map(Epd::E2in9),
+            Panel::Epd2in9bc => EPD2in9bc::new(spi, cs, busy, dc, rst, delay).map(Epd::E2in9bc),
+            Panel::Epd7in5 => EPD7in5::new(spi, cs, busy, dc, rst, delay).map(Epd::E7in5),
+            Panel::Epd7in5V2 => {
+                epd7in5_v2::EPD7in5::new(spi, cs, busy, dc, rst, delay).map(Epd::E7in5V2)
+            }
+        }
+        .map_err(Oops::display)
+    }
+
+    // the 7in5 drivers only have one waveform and panic if asked for another,
+    // the 2in9bc's ignores it
+    fn set_lut(&mut self, spi: &mut SPI, lut: RefreshLUT) -> Result<()> {
+        match self {
+            Epd::E2in9(epd) => epd.set_lut(spi, Some(lut)).map_err(Oops::display),
+            Epd::E2in9bc(_) | Epd::E7in5(_) | Epd::E7in5V2(_) => Ok(()),
+        }
+    }
+
+    // the colour layer blanked on a panel that has one
+    fn update_and_display_frame(&mut self, spi: &mut SPI, buffer: &[u8]) -> Result<()> {
+        match self {
+            Epd::E2in9(epd) => epd.update_and_display_frame(spi, buffer),
+            Epd::E2in9bc(epd) => epd.update_and_display_frame(spi, buffer),
+            Epd::E7in5(epd) => epd.update_and_display_frame(spi, buffer),
+            Epd::E7in5V2(epd) => epd.update_and_display_frame(spi, buffer),
+        }
+        .map_err(Oops::display)
+    }
+
+    // Black and white from `black` and the panel's colour where `color` is
+    // black, which wins over `black`. Just `black` on a panel without one.
+    fn update_and_display_color_frame(
+        &mut self,
+        spi: &mut SPI,
+        black: &[u8],
+        color: &[u8],
+    ) -> Result<()> {
+        match self {
+            Epd::E2in9bc(epd) => epd
+                .update_color_frame(spi, black, color)
+                .and_then(|()| epd.display_frame(spi))
+                .map_err(Oops::display),
+            Epd::E2in9(_) | Epd::E7in5(_) | Epd::E7in5V2(_) => {
+                self.update_and_display_frame(spi, black)
+            }
+        }
+    }
+
+    fn sleep(&mut self, spi: &mut SPI) -> Result<()> {
+        match self {
+            Epd::E2in9(epd) => epd.sleep(spi),
+            Epd::E2in9bc(epd) => epd.sleep(spi),
+            Epd::E7in5(epd) => epd.sleep(spi),
+            Epd::E7in5V2(epd) => epd.sleep(spi),
+        }
+        .map_err(Oops::display)
+    }
+}
+
+// The BUSY pin, cutting short a wait that's gone on past busy_timeout_secs.
+// The drivers spin on it with no way out, but take an error reading it as
+// idle, so that's how the wait gets ended. Once one has timed out every read
+// fails straight away, the rest of the call is pointless with the controller
+// stuck, and AwakeEpd sees timed_out to fail the call.
+struct BusyPin {
+    pin: Pin,
+    timeout: Duration,
+    busy_since: Cell<Option<Instant>>,
+    timed_out: Rc<Cell<bool>>,
+}
+
+impl BusyPin {
+    // the driver reads it whichever way round means busy for its panel, so
+    // true is another read of the same wait either way
+    fn poll(&self, busy: bool) -> result::Result<bool, sysfs_gpio::Error> {
+        if self.timed_out.get() {
+            return Err(sysfs_gpio::Error::Unexpected("EPD timed out".to_string()));
+        }
+        if !busy {
+            self.busy_since.set(None);
+            return Ok(false);
+        }
+        let since = self.busy_since.get().unwrap_or_else(Instant::now);
+        if since.elapsed() > self.timeout {
+            error!(
+                "EPD still busy after {}s, giving up on it",
+                self.timeout.as_secs()
+            );
+            self.timed_out.set(true);
+            return Err(sysfs_gpio::Error::Unexpected("EPD timed out".to_string()));
+        }
+        self.busy_since.set(Some(since));
+        Ok(true)
+    }
+}
+
+impl InputPin for BusyPin {
+    type Error = sysfs_gpio::Error;
+
+    fn is_high(&self) -> result::Result<bool, Self::Error> {
+        self.poll(self.pin.is_high()?)
+    }
+
+    fn is_low(&self) -> result::Result<bool, Self::Error> {
+        self.poll(self.pin.is_low()?)
+    }
+}
+
+// The EPD as handed out by get_epd. It goes back to sleep when dropped if
+// sleep wasn't called, so an error or panic part way through a refresh
+// doesn't leave the panel powered. Each call fails if the EPD stayed busy
+// too long, see BusyPin.
+struct AwakeEpd<SPI = Spidev, CS = Pin, BUSY = BusyPin, DC = Pin, RST = Pin>
+where
+    SPI: Write<u8>,
+    SPI::Error: fmt::Display,
+    CS: OutputPin,
+    BUSY: InputPin,
+    DC: OutputPin,
+    RST: OutputPin,
+{
+    epd: Epd<SPI, CS, BUSY, DC, RST>,
+    spi: SPI,
+    asleep: bool,
+    busy_timed_out: Rc<Cell<bool>>,
+    busy_timeout: Duration,
+}
+
+impl<SPI, CS, BUSY, DC, RST> AwakeEpd<SPI, CS, BUSY, DC, RST>
+where
+    SPI: Write<u8>,
+    SPI::Error: fmt::Display,
+    CS: OutputPin,
+    BUSY: InputPin,
+    DC: OutputPin,
+    RST: OutputPin,
+{
+    // the driver's reset waits on BUSY too, so that's checked here
+    fn new(
+        epd: Epd<SPI, CS, BUSY, DC, RST>,
+        spi: SPI,
+        busy_timed_out: Rc<Cell<bool>>,
+        busy_timeout: Duration,
+    ) -> Result<Self> {
+        let awake = AwakeEpd {
+            epd,
+            spi,
+            asleep: false,
+            busy_timed_out,
+            busy_timeout,
+        };
+        awake.check_busy()?;
+        Ok(awake)
+    }
+
+    fn set_lut(&mut self, lut: RefreshLUT) -> Result<()> {
+        self.epd.set_lut(&mut self.spi, lut)?;
+        self.check_busy()
+    }
+
+    fn update_and_display_frame(&mut self, buffer: &[u8]) -> Result<()> {
+        self.epd.update_and_display_frame(&mut self.spi, buffer)?;
+        self.check_busy()
+    }
+
+    fn update_and_display_color_frame(&mut self, black: &[u8], color: &[u8]) -> Result<()> {
+        self.epd
+            .update_and_display_color_frame(&mut self.spi, black, color)?;
+        self.check_busy()
+    }
+
+    fn sleep(mut self) -> Result<()> {
+        self.asleep = true;
+        self.epd.sleep(&mut self.spi)?;
+        self.check_busy()
+    }
+
+    fn check_busy(&self) -> Result<()> {
+        if self.busy_timed_out.get() {
+            return Err(Oops::Display(format!(
+                "EPD stayed busy for over {}s, check its wiring",
+                self.busy_timeout.as_secs()
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl<SPI, CS, BUSY, DC, RST> Drop for AwakeEpd<SPI, CS, BUSY, DC, RST>
+where
+    SPI: Write<u8>,
+    SPI::Error: fmt::Display,
+    CS: OutputPin,
+    BUSY: InputPin,
+    DC: OutputPin,
+    RST: OutputPin,
+{
+    fn drop(&mut self) {
+        if self.asleep {
+            return;
+        }
+        match self.epd.sleep(&mut self.spi) {
+            Ok(()) => info!("EPD put to sleep after a failed refresh"),
+            Err(e) => error!("failed to put the EPD to sleep: {}", e),
+        }
+    }
+}
+
+// how many days the forecast strip shows
+const FORECAST_DAYS: usize = 3;
+
+// the location strip above the outdoor and forecast panels
+const HEADER_HEIGHT: i32 = 10;
+
+// the strip under the forecast with the time it was drawn
+const FOOTER_HEIGHT: i32 = 10;
+
+// the rain banner across the bottom, see rain_banner_threshold
+const BANNER_HEIGHT: i32 = 10;
+
+// shown in place of any value we couldn't fetch
+const PLACEHOLDER: &str = "--";
+
+// modules of light around the QR code, half what the spec asks for as the
+// panel's mostly white around it anyway
+#[cfg(feature = "qr")]
+const QR_QUIET_ZONE: i32 = 2;
+
+// in the cache dir, what run_quiet last put on the panel, empty when it was
+// cleared, and gone once anything else has been
+const QUIET_DRAWN: &str = "quiet_drawn.txt";
+
+/// What to do with a frame instead of showing it on the EPD
+#[derive(Clone, Copy)]
+pub enum DryRun<'a> {
+    /// write it to this path as a PNG
+    Png(&'a str),
+    /// print it to stdout with block characters, see buffer_to_ascii
+    Preview,
+}
+
+/// Fetches everything, draws it and pushes it to the panel, skipping the
+/// refresh when nothing moved since last time. With `dry_run` the frame goes
+/// there instead, always drawn, and the EPD is left alone.
+pub fn run(config: &Config, now: DateTime<Local>, dry_run: Option<DryRun>) -> Result<()> {
+    run_reusing(config, now, dry_run, &mut Vec::new())
+}
+
+/// Like run, drawing into `frame` as render_into does, for running over and
+/// over without allocating a frame each time
+pub fn run_reusing(
+    config: &Config,
+    now: DateTime<Local>,
+    dry_run: Option<DryRun>,
+    frame: &mut Vec<u8>,
+) -> Result<()> {
+    run_summarized(config, now, dry_run, frame, &mut RunSummary::new(now))
+}
+
+/// Like run_reusing, noting in `summary` how each source went and what
+/// became of the frame. It's left for the caller to fill in a failure.
+pub fn run_summarized(
+    config: &Config,
+    now: DateTime<Local>,
+    dry_run: Option<DryRun>,
+    frame: &mut Vec<u8>,
+    summary: &mut RunSummary,
+) -> Result<()> {
+    let agent = http_agent(config);
+    let influx_agent = influx_agent(config)?;
+    let indoor_source = indoor_source(config, &influx_agent);
+    let weather_source = weather_source(config, &agent, now);
+    let sources = RefCell::new(Vec::new());
+    let data = get_data(
+        &Timed::new(indoor_source.as_ref(), &sources),
+        &Timed::new(weather_source.as_ref(), &sources),
+        config.show_forecast,
+    );
+    summary.sources = sources.into_inner();
+    let mut data = data?;
+    data.battery = get_battery_data(config);
+    data.indoor_history = get_indoor_history(&influx_agent, config, &data);
+    data.pressure_trend = get_pressure_trend(&influx_agent, config, &data);
+    info!("data fetched");
+
+    if let Some(path) = &config.prometheus_path {
+        if let Err(e) = write_prometheus(path, &data, config.units) {
+            warn!("failed to write {}: {}", path.display(), e);
+        }
+    }
+
+    let drawn = LastDrawn::new(config, data, now);
+    let last_drawn_path = cache_dir().join("last_drawn.json");
+    if dry_run.is_none() {
+        if let Some(last) = load_last_drawn(&last_drawn_path) {
+            if !changed(&last, &drawn, &config.change_epsilon) {
+                info!("nothing changed, skipping refresh");
+                summary.outcome = Outcome::Unchanged;
+                return Ok(());
+            }
+        }
+    }
+
+    render_into(config, &drawn.data, now, frame)?;
+    let accents = render_accents(config, &drawn.data, now)?;
+    info!("frame drawn");
+
+    let (width, height) = config.panel.size();
+    if dry_run.is_some() {
+        summary.outcome = Outcome::DryRun;
+    }
+    match dry_run {
+        Some(DryRun::Png(path)) => {
+            write_png(
+                path,
+                frame,
+                accents.as_deref(),
+                width,
+                height,
+                config.rotation(),
+            )?;
+            info!("dry run, wrote {}", path);
+            return Ok(());
+        }
+        Some(DryRun::Preview) => {
+            print!(
+                "{}",
+                buffer_to_ascii(frame, width, height, config.rotation())
+            );
+            return Ok(());
+        }
+        None => {}
+    }
+
+    let mut awake = get_epd(config)?;
+
+    // Display updated frame
+    refresh(&mut awake, frame, accents.as_deref(), config)?;
+    info!("display refreshed");
+    summary.outcome = Outcome::Refreshed;
+    save_last_drawn(&last_drawn_path, &drawn)?;
+    let _ = fs::remove_file(cache_dir().join(QUIET_DRAWN));
+
+    // Set the EPD to sleep
+    awake.sleep()
+}
+
+/// How a run went, for log aggregation. With json_summary it's printed as a
+/// line of JSON on stdout once the run's over.
+#[derive(Serialize)]
+pub struct RunSummary {
+    pub timestamp: DateTime<Local>,
+    /// skipped for quiet hours
+    pub quiet: bool,
+    pub outcome: Outcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// how each fetch went, in the order they ran. Empty if the run didn't
+    /// get as far as fetching.
+    pub sources: Vec<SourceSummary>,
+}
+
+impl RunSummary {
+    /// A summary for a run that's yet to happen, failed until it says
+    /// otherwise
+    pub fn new(timestamp: DateTime<Local>) -> RunSummary {
+        RunSummary {
+            timestamp,
+            quiet: false,
+            outcome: Outcome::Failed,
+            error: None,
+            sources: Vec::new(),
+        }
+    }
+}
+
+/// What became of a run's frame
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    /// shown on the EPD
+    Refreshed,
+    /// nothing changed since the last refresh, so the EPD was left alone
+    Unchanged,
+    /// drawn for --dry-run or --preview
+    DryRun,
+    /// quiet hours, see quiet_mode
+    Quiet,
+    Failed,
+}
+
+/// One fetch in a RunSummary
+#[derive(Serialize)]
+pub struct SourceSummary {
+    /// "indoor", "outdoor" or "forecast"
+    pub name: &'static str,
+    pub ok: bool,
+    pub millis: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+// times each fetch through a source, noting it in `sources`
+struct Timed<'a, S: ?Sized> {
+    source: &'a S,
+    sources: &'a RefCell<Vec<SourceSummary>>,
+}
+
+impl<'a, S: ?Sized> Timed<'a, S> {
+    fn new(source: &'a S, sources: &'a RefCell<Vec<SourceSummary>>) -> Timed<'a, S> {
+        Timed { source, sources }
+    }
+
+    fn time<T>(&self, name: &'static str, fetch: impl FnOnce(&S) -> Result<T>) -> Result<T> {
+        let start = Instant::now();
+        let result = fetch(self.source);
+        self.sources.borrow_mut().push(SourceSummary {
+            name,
+            ok: result.is_ok(),
+            millis: start.elapsed().as_millis() as u64,
+            error: result.as_ref().err().map(|e| e.to_string()),
+        });
+        result
+    }
+}
+
+impl IndoorSource for Timed<'_, dyn IndoorSource + '_> {
+    fn indoor(&self) -> Result<Vec<IndoorData>> {
+        self.time("indoor", |source| source.indoor())
+    }
+}
+
+impl WeatherSource for Timed<'_, dyn WeatherSource + '_> {
+    fn outdoor(&self) -> Result<OutdoorData> {
+        self.time("outdoor", |source| source.outdoor())
+    }
+
+    fn forecast(&self) -> Result<ForecastData> {
+        self.time("forecast", |source| source.forecast())
+    }
+}
+
+// What run_quiet should put on the panel, empty to clear it, or None with
+// quiet_mode skip or when `shown`, what it last put there, is the same
+fn quiet_text(config: &Config, now: DateTime<Local>, shown: Option<&str>) -> Option<String> {
+    let text = match config.quiet_mode {
+        QuietMode::Skip => return None,
+        QuietMode::Clear => String::new(),
+        QuietMode::Clock => now.format(&config.updated_format).to_string(),
+    };
+    if shown == Some(text.as_str()) {
+        info!("quiet hours frame already shown");
+        return None;
+    }
+    Some(text)
+}
+
+/// Run in place of run through quiet hours, doing what quiet_mode says.
+/// Nothing's fetched, and the panel's only touched when what quiet hours
+/// show has changed, so a cleared panel is cleared once a night.
+pub fn run_quiet(config: &Config, now: DateTime<Local>) -> Result<()> {
+    let quiet_drawn = cache_dir().join(QUIET_DRAWN);
+    let shown = fs::read_to_string(&quiet_drawn).ok();
+    let text = match quiet_text(config, now, shown.as_deref()) {
+        Some(text) => text,
+        None => return Ok(()),
+    };
+
+    if text.is_empty() {
+        clear(config)?;
+    } else {
+        let (width, height) = config.panel.size();
+        let mut buffer = blank_buffer(config.panel);
+        let mut display = VarDisplay::new(width, height, &mut buffer);
+        display.set_rotation(config.rotation());
+        let (canvas_width, canvas_height) = canvas_size(&display);
+        let canvas = Rectangle::new(Point::zero(), Point::new(canvas_width, canvas_height));
+        draw_centered(&mut display, &text, canvas, FontChoice::Font24x32)?;
+        apply_invert(&mut display, config);
+        // so the first run after quiet hours draws the readings back
+        let _ = fs::remove_file(cache_dir().join("last_drawn.json"));
+        let mut awake = get_epd(config)?;
+        refresh(&mut awake, display.buffer(), None, config)?;
+        awake.sleep()?;
+    }
+    info!("quiet hours frame shown");
+    fs::write(quiet_drawn, text)?;
+    Ok(())
+}
+
+/// Draws `data` into a new frame buffer for the configured panel, ready to
+/// send to it. Nothing is fetched and the EPD isn't touched.
+pub fn render(config: &Config, data: &Data, now: DateTime<Local>) -> Result<Vec<u8>> {
+    let mut frame = Vec::new();
+    render_into(config, data, now, &mut frame)?;
+    Ok(frame)
+}
+
+/// Like render, but into `frame`, which is blanked first. Once it's been used
+/// for a frame it has the room for the next, so nothing is allocated. That
+/// saves an allocation per refresh, 4.7kB for the 2in9 and 48kB for the 7in5
+/// v2. The drawing itself takes far longer, benches/draw.rs times both.
+pub fn render_into(
+    config: &Config,
+    data: &Data,
+    now: DateTime<Local>,
+    frame: &mut Vec<u8>,
+) -> Result<()> {
+    // Use display graphics from embedded-graphics
+    let (width, height) = config.panel.size();
+    clear_buffer(frame, config.panel);
+    let mut display = VarDisplay::new(width, height, frame);
+    display.set_rotation(config.rotation());
+    draw(&mut display, config, data, now)?;
+    apply_invert(&mut display, config);
+    Ok(())
+}
+
+// The colour layer to go with render_into's frame with color_accents, black
+// where the colour goes, or None without it
+fn render_accents(config: &Config, data: &Data, now: DateTime<Local>) -> Result<Option<Vec<u8>>> {
+    if !config.color_accents {
+        return Ok(None);
+    }
+    let (width, height) = config.panel.size();
+    let mut accents = blank_buffer(config.panel);
+    let mut display = VarDisplay::new(width, height, &mut accents);
+    display.set_rotation(config.rotation());
+    draw_accents(&mut display, config, data, now)?;
+    Ok(Some(accents))
+}
+
+/// Replaces whatever is on the panel with `msg`, so a failed run doesn't leave
+/// old readings up looking current. The saved state is dropped so the next
+/// good run redraws in full.
+pub fn show_error(config: &Config, msg: &str) -> Result<()> {
+    let (width, height) = config.panel.size();
+    let mut buffer = blank_buffer(config.panel);
+    let mut display = VarDisplay::new(width, height, &mut buffer);
+    display.set_rotation(config.rotation());
+    draw_error(&mut display, msg)?;
+    apply_invert(&mut display, config);
+    replace_frame(config, display.buffer())
+}
+
+/// Replaces whatever is on the panel with `text` as big as it fits, wrapped
+/// onto as many lines as it needs, like a notification from a script. It
+/// stays up until the next refresh redraws the readings in full.
+pub fn show_message(config: &Config, text: &str) -> Result<()> {
+    let (width, height) = config.panel.size();
+    let mut buffer = blank_buffer(config.panel);
+    let mut display = VarDisplay::new(width, height, &mut buffer);
+    display.set_rotation(config.rotation());
+    draw_message(&mut display, text)?;
+    apply_invert(&mut display, config);
+    replace_frame(config, display.buffer())
+}
+
+/// Blanks the panel, for leaving it empty rather than showing old readings
+/// while it's not being updated
+pub fn clear(config: &Config) -> Result<()> {
+    replace_frame(config, &blank_buffer(config.panel))
+}
+
+// A full refresh to `buffer` outside of a normal run, forgetting what was
+// last drawn
+fn replace_frame(config: &Config, buffer: &[u8]) -> Result<()> {
+    forget_drawn();
+    let mut awake = get_epd(config)?;
+    awake.set_lut(RefreshLUT::FULL)?;
+    awake.update_and_display_frame(buffer)?;
+    awake.sleep()
+}
+
+// so the next run draws everything again with a full refresh
+fn forget_drawn() {
+    for state in ["last_drawn.json", "frame.bin", QUIET_DRAWN] {
+        let _ = fs::remove_file(cache_dir().join(state));
+    }
+}
+
+/// Shows draw_test_pattern with a full refresh, for checking the SPI and GPIO
+/// wiring with nothing fetched. How long the EPD took to set up and to show
+/// the pattern is logged.
+pub fn selftest(config: &Config) -> Result<()> {
+    let (width, height) = config.panel.size();
+    let mut buffer = blank_buffer(config.panel);
+    let mut display = VarDisplay::new(width, height, &mut buffer);
+    display.set_rotation(config.rotation());
+    draw_test_pattern(&mut display)?;
+    forget_drawn();
+
+    let start = Instant::now();
+    let mut awake = get_epd(config)?;
+    info!("EPD set up in {:.2?}", start.elapsed());
+    let start = Instant::now();
+    awake.set_lut(RefreshLUT::FULL)?;
+    awake.update_and_display_frame(display.buffer())?;
+    info!("test pattern shown in {:.2?}", start.elapsed());
+    awake.sleep()
+}
+
+// A border around the edge, then a checkerboard down the left half and a line
+// in each font down the right, so a shifted, clipped or noisy frame stands out
+fn draw_test_pattern(display: &mut VarDisplay) -> Result<()> {
+    const SQUARE: i32 = 8;
+    let (width, height) = canvas_size(display);
+    let black = PrimitiveStyleBuilder::new().fill_color(Black).build();
+    let border = PrimitiveStyleBuilder::new()
+        .stroke_color(Black)
+        .stroke_width(1)
+        .build();
+    Rectangle::new(Point::zero(), Point::new(width - 1, height - 1))
+        .into_styled(border)
+        .draw(display)?;
+
+    let half = width / 2;
+    for row in 0..(height - 2) / SQUARE {
+        for column in (0..(half - 2) / SQUARE).filter(|column| (row + column) % 2 == 0) {
+            let corner = Point::new(2 + column * SQUARE, 2 + row * SQUARE);
+            Rectangle::new(corner, corner + Point::new(SQUARE - 1, SQUARE - 1))
+                .into_styled(black)
+                .draw(display)?;
+        }
+    }
+
+    const SAMPLE: &str = "Aa09";
+    let heights: Vec<i32> = FontChoice::ALL
+        .iter()
+        .map(|font| font.character_size().height as i32 + 2)
+        .collect();
+    let mut top = (height - heights.iter().sum::<i32>()) / 2;
+    for (&font, row_height) in FontChoice::ALL.iter().zip(heights) {
+        let row = Rectangle::new(
+            Point::new(half + 2, top),
+            Point::new(width - 3, top + row_height - 1),
+        );
+        draw_centered(display, SAMPLE, row, font)?;
+        top += row_height;
+    }
+    Ok(())
+}
+
+// White on black with invert set, by flipping every pixel of a finished frame
+fn apply_invert(display: &mut VarDisplay, config: &Config) {
+    if config.invert {
+        for byte in display.get_mut_buffer() {
+            *byte = !*byte;
+        }
+    }
+}
+
+// a white frame the size of the panel's buffer
+fn blank_buffer(panel: Panel) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    clear_buffer(&mut buffer, panel);
+    buffer
+}
+
+// whites out a buffer to the panel's size, keeping its allocation
+fn clear_buffer(buffer: &mut Vec<u8>, panel: Panel) {
+    let (width, height) = panel.size();
+    buffer.clear();
+    buffer.resize((width / 8 * height) as usize, Color::White.get_byte_value());
+}
+
+/// Where the indoor readings come from
+pub trait IndoorSource {
+    fn indoor(&self) -> Result<Vec<IndoorData>>;
+}
+
+/// Where the outdoor readings and forecast come from. They're separate so a
+/// source can fail one without the other.
+pub trait WeatherSource {
+    fn outdoor(&self) -> Result<OutdoorData>;
+    fn forecast(&self) -> Result<ForecastData>;
+}
+
+/// Only fails if every source failed, otherwise the failures are logged and
+/// those panels are drawn with a placeholder. The forecast's only fetched
+/// with `show_forecast`.
+pub fn get_data(
+    indoor_source: &dyn IndoorSource,
+    weather_source: &dyn WeatherSource,
+    show_forecast: bool,
+) -> Result<Data> {
+    let results = (
+        indoor_source.indoor(),
+        weather_source.outdoor(),
+        show_forecast.then(|| weather_source.forecast()),
+    );
+    let (indoor, outdoor, forecast) = match results {
+        (Err(indoor_err), Err(outdoor_err), None) => {
+            error!("failed to get indoor data: {}", indoor_err);
+            return Err(outdoor_err);
+        }
+        (Err(indoor_err), Err(outdoor_err), Some(Err(forecast_err))) => {
+            error!("failed to get indoor data: {}", indoor_err);
+            error!("failed to get outdoor data: {}", outdoor_err);
+            return Err(forecast_err);
+        }
+        results => results,
+    };
+
+    Ok(Data {
+        indoor: indoor
+            .map_err(|e| error!("failed to get indoor data: {}", e))
+            .unwrap_or_default(),
+        outdoor: outdoor
+            .map_err(|e| error!("failed to get outdoor data: {}", e))
+            .ok(),
+        forecast: forecast.and_then(|forecast| {
+            forecast
+                .map_err(|e| error!("failed to get forecast data: {}", e))
+                .ok()
+        }),
+        battery: None,
+        indoor_history: Vec::new(),
+        pressure_trend: None,
+    })
+}
+
+#[cfg(feature = "battery")]
+fn get_battery_data(config: &Config) -> Option<BatteryData> {
+    read_max17048(&config.battery_i2c_bus)
+        .map_err(|e| {
+            error!(
+                "failed to read battery on {}: {}",
+                config.battery_i2c_bus, e
+            )
+        })
+        .ok()
+}
+
+#[cfg(not(feature = "battery"))]
+fn get_battery_data(_config: &Config) -> Option<BatteryData> {
+    None
+}
+
+#[cfg(feature = "battery")]
+fn read_max17048(
+    bus: &str,
+) -> result::Result<BatteryData, linux_embedded_hal::i2cdev::linux::LinuxI2CError> {
+    use embedded_hal::blocking::i2c::WriteRead;
+    use linux_embedded_hal::{i2cdev::linux::LinuxI2CError, I2cdev};
+
+    const ADDRESS: u8 = 0x36;
+    const VCELL: u8 = 0x02;
+    const SOC: u8 = 0x04;
+
+    let mut i2c = I2cdev::new(bus)?;
+    let mut read = |register: u8| -> result::Result<u16, LinuxI2CError> {
+        let mut buffer = [0; 2];
+        i2c.write_read(ADDRESS, &[register], &mut buffer)?;
+        Ok(u16::from_be_bytes(buffer))
+    };
+
+    Ok(BatteryData {
+        // 78.125uV per bit
+        voltage: read(VCELL)? as f64 * 78.125e-6,
+        // high byte is whole percent, low byte is 1/256ths
+        percent: read(SOC)? as f64 / 256.0,
+    })
+}
+
+/// Indoor readings from the `indoor` measurement in InfluxDB
+pub struct InfluxDb<'a> {
+    config: &'a Config,
+    agent: &'a ureq::Agent,
+}
+
+impl<'a> InfluxDb<'a> {
+    pub fn new(config: &'a Config, agent: &'a ureq::Agent) -> InfluxDb<'a> {
+        InfluxDb { config, agent }
+    }
+}
+
+// A room that fails is logged and left out, it's only an error when every
+// room failed
+impl IndoorSource for InfluxDb<'_> {
+    fn indoor(&self) -> Result<Vec<IndoorData>> {
+        let mut rooms = Vec::new();
+        let mut last_err = None;
+        for source in &self.config.indoor_sources {
+            match self.room(source) {
+                Ok(indoor) => rooms.push(indoor),
+                Err(e) => {
+                    warn!("failed to get indoor data for {}: {}", source.name, e);
+                    last_err = Some(e);
+                }
+            }
+        }
+        match last_err {
+            Some(e) if rooms.is_empty() => Err(e),
+            _ => Ok(rooms),
+        }
+    }
+}
+
+impl InfluxDb<'_> {
+    fn room(&self, source: &IndoorSourceConfig) -> Result<IndoorData> {
+        let config = self.config;
+        let dialect = influx_dialect(config);
+        let query = dialect.latest_query(config, source);
+        let body = dialect.send(self.agent, config, &query)?;
+        match dialect.parse_latest(&body)? {
+            Some(row) => Ok(indoor_data(
+                &source.name,
+                row.calibrated(config),
+                config.units,
+            )),
+            None => Err(Oops::Data(format!(
+                "InfluxDB returned no indoor data for the last {}",
+                config.influx_window
+            ))),
+        }
+    }
+}
+
+fn indoor_source<'a>(config: &'a Config, agent: &'a ureq::Agent) -> Box<dyn IndoorSource + 'a> {
+    match config.indoor_source {
+        IndoorSourceKind::Influx => Box::new(InfluxDb::new(config, agent)),
+        #[cfg(feature = "mqtt")]
+        IndoorSourceKind::Mqtt => Box::new(Mqtt::new(config)),
+    }
+}
+
+/// Indoor readings from the retained messages on an MQTT broker, only with
+/// the mqtt feature. There's just the one room, and no sample time or
+/// history, so it's never drawn as stale and there's no sparkline or trend.
+#[cfg(feature = "mqtt")]
+pub struct Mqtt<'a> {
+    config: &'a Config,
+}
+
+#[cfg(feature = "mqtt")]
+impl<'a> Mqtt<'a> {
+    pub fn new(config: &'a Config) -> Mqtt<'a> {
+        Mqtt { config }
+    }
+}
+
+// A topic without a retained message leaves its field None, it's only an
+// error when none of them had one
+#[cfg(feature = "mqtt")]
+impl IndoorSource for Mqtt<'_> {
+    fn indoor(&self) -> Result<Vec<IndoorData>> {
+        let config = self.config;
+        let mqtt = config
+            .mqtt
+            .as_ref()
+            .ok_or_else(|| Oops::Config("mqtt isn't set".to_string()))?;
+        let topics = mqtt.topics();
+        let names: Vec<&str> = topics.iter().map(|(_, topic)| *topic).collect();
+        let messages = mqtt_retained(config, mqtt, &names)?;
+        if messages.is_empty() {
+            return Err(Oops::Data(format!(
+                "no retained messages on {}",
+                names.join(", ")
+            )));
+        }
+        let row = IndoorRow::from_fields(None, |field| {
+            let (_, topic) = topics.iter().find(|(name, _)| *name == field)?;
+            let payload = messages.get(*topic)?;
+            let value = std::str::from_utf8(payload).ok()?.trim().parse().ok();
+            if value.is_none() {
+                warn!(
+                    "{} isn't a number: {:?}",
+                    topic,
+                    String::from_utf8_lossy(payload)
+                );
+            }
+            value
+        });
+        Ok(vec![indoor_data(
+            &mqtt.name,
+            row.calibrated(config),
+            config.units,
+        )])
+    }
+}
+
+#[cfg(feature = "mqtt")]
+impl MqttConfig {
+    // the IndoorRow field each configured topic goes in
+    fn topics(&self) -> Vec<(&'static str, &str)> {
+        [
+            ("temperature", &self.temperature_topic),
+            ("humidity", &self.humidity_topic),
+            ("pressure", &self.pressure_topic),
+        ]
+        .into_iter()
+        .filter_map(|(field, topic)| Some((field, topic.as_deref()?)))
+        .collect()
+    }
+}
+
+// how long to wait for retained messages once subscribed, they come straight
+// away so any topic still missing after this doesn't have one
+#[cfg(feature = "mqtt")]
+const MQTT_RETAINED_WAIT: Duration = Duration::from_secs(2);
+
+// The latest payload on each topic, from a bare MQTT 3.1.1 session at QoS 0:
+// connect, subscribe, read what the broker has retained and disconnect.
+// That's little enough not to pull in a client and an async runtime for.
+#[cfg(feature = "mqtt")]
+fn mqtt_retained(
+    config: &Config,
+    mqtt: &MqttConfig,
+    topics: &[&str],
+) -> Result<BTreeMap<String, Vec<u8>>> {
+    use std::net::{TcpStream, ToSocketAddrs};
+
+    let broker = format!("{}:{}", mqtt.host, mqtt.port);
+    let io_error = |e: std::io::Error| match e.kind() {
+        std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock => {
+            Oops::Timeout(format!("MQTT broker {}", broker))
+        }
+        _ => Oops::Io(e),
+    };
+    let address = (mqtt.host.as_str(), mqtt.port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| Oops::Config(format!("mqtt host {:?} has no address", mqtt.host)))?;
+    let mut stream =
+        TcpStream::connect_timeout(&address, Duration::from_secs(config.timeout_connect_secs))
+            .map_err(io_error)?;
+    let read_timeout = Duration::from_secs(config.timeout_read_secs);
+    stream.set_read_timeout(Some(read_timeout))?;
+    stream.set_write_timeout(Some(read_timeout))?;
+
+    // CONNECT with a clean session, so nothing's kept for us after
+    let mut flags = 0x02;
+    let mut payload = mqtt_string(&mqtt.client_id);
+    if let Some(username) = &mqtt.username {
+        flags |= 0x80;
+        payload.extend(mqtt_string(username));
+    }
+    if let Some(password) = &mqtt.password {
+        flags |= 0x40;
+        payload.extend(mqtt_string(password));
+    }
+    let mut connect = mqtt_string("MQTT");
+    connect.extend([4, flags, 0, MQTT_KEEP_ALIVE_SECS]);
+    connect.extend(payload);
+    write_mqtt_packet(&mut stream, 0x10, &connect).map_err(io_error)?;
+    match read_mqtt_packet(&mut stream).map_err(io_error)? {
+        (0x20, connack) if connack.len() == 2 && connack[1] == 0 => {}
+        (0x20, connack) if connack.len() == 2 => {
+            return Err(Oops::Data(format!(
+                "MQTT broker {} refused the connection: {}",
+                broker,
+                mqtt_connack_reason(connack[1])
+            )))
+        }
+        _ => return Err(Oops::Data(format!("MQTT broker {} didn't CONNACK", broker))),
+    }
+
+    // SUBSCRIBE, packet id 1, at QoS 0
+    let mut subscribe = vec![0, 1];
+    for topic in topics {
+        subscribe.extend(mqtt_string(topic));
+        subscribe.push(0);
+    }
+    write_mqtt_packet(&mut stream, 0x82, &subscribe).map_err(io_error)?;
+
+    // the broker can send retained messages before the SUBACK, so they're
+    // read in the same loop, and the wait shortened once it's come
+    let mut messages = BTreeMap::new();
+    let mut subscribed = false;
+    while !subscribed || messages.len() < topics.len() {
+        let (header, body) = match read_mqtt_packet(&mut stream) {
+            Ok(packet) => packet,
+            Err(e)
+                if subscribed
+                    && matches!(
+                        e.kind(),
+                        std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock
+                    ) =>
+            {
+                break
+            }
+            Err(e) => return Err(io_error(e)),
+        };
+        match header >> 4 {
+            // SUBACK, a return code of 0x80 is a topic we weren't let have
+            9 => {
+                if body.iter().skip(2).any(|&code| code == 0x80) {
+                    return Err(Oops::Data(format!(
+                        "MQTT broker {} refused the subscription",
+                        broker
+                    )));
+                }
+                subscribed = true;
+                stream.set_read_timeout(Some(read_timeout.min(MQTT_RETAINED_WAIT)))?;
+            }
+            // PUBLISH, with a packet id after the topic above QoS 0
+            3 => {
+                let (topic, rest) = mqtt_split_string(&body)
+                    .ok_or_else(|| Oops::Data(format!("bad PUBLISH from {}", broker)))?;
+                let qos = (header >> 1) & 0x03;
+                let payload = if qos > 0 { rest.get(2..) } else { Some(rest) };
+                if let Some(payload) = payload {
+                    messages.insert(topic, payload.to_vec());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // DISCONNECT, the broker drops the session either way
+    let _ = write_mqtt_packet(&mut stream, 0xe0, &[]);
+    Ok(messages)
+}
+
+// we're only connected for a moment, but 0 would turn keep alive off
+#[cfg(feature = "mqtt")]
+const MQTT_KEEP_ALIVE_SECS: u8 = 60;
+
+// nothing we subscribe to should come anywhere near this, it's there so a
+// confused broker can't have us allocate gigabytes
+#[cfg(feature = "mqtt")]
+const MQTT_MAX_PACKET: usize = 1 << 20;
+
+// strings are length prefixed, big endian
+#[cfg(feature = "mqtt")]
+fn mqtt_string(s: &str) -> Vec<u8> {
+    let mut bytes = (s.len() as u16).to_be_bytes().to_vec();
+    bytes.extend(s.as_bytes());
+    bytes
+}
+
+#[cfg(feature = "mqtt")]
+fn mqtt_split_string(bytes: &[u8]) -> Option<(String, &[u8])> {
+    let len = u16::from_be_bytes([*bytes.first()?, *bytes.get(1)?]) as usize;
+    let s = std::str::from_utf8(bytes.get(2..2 + len)?).ok()?;
+    Some((s.to_string(), &bytes[2 + len..]))
+}
+
+// the fixed header is the packet type and flags, then the remaining length
+// 7 bits at a time, least significant first
+#[cfg(feature = "mqtt")]
+fn write_mqtt_packet(
+    stream: &mut impl std::io::Write,
+    header: u8,
+    body: &[u8],
+) -> std::io::Result<()> {
+    let mut packet = vec![header];
+    let mut len = body.len();
+    loop {
+        let byte = (len % 128) as u8;
+        len /= 128;
+        if len == 0 {
+            packet.push(byte);
+            break;
+        }
+        packet.push(byte | 0x80);
+    }
+    packet.extend(body);
+    stream.write_all(&packet)
+}
+
+#[cfg(feature = "mqtt")]
+fn read_mqtt_packet(stream: &mut impl std::io::Read) -> std::io::Result<(u8, Vec<u8>)> {
+    let mut byte = [0];
+    stream.read_exact(&mut byte)?;
+    let header = byte[0];
+    let mut len = 0;
+    for shift in (0..28).step_by(7) {
+        stream.read_exact(&mut byte)?;
+        len |= ((byte[0] & 0x7f) as usize) << shift;
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+    }
+    if len > MQTT_MAX_PACKET {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("MQTT packet of {} bytes", len),
+        ));
+    }
+    let mut body = vec![0; len];
+    stream.read_exact(&mut body)?;
+    Ok((header, body))
+}
+
+#[cfg(feature = "mqtt")]
+fn mqtt_connack_reason(code: u8) -> &'static str {
+    match code {
+        1 => "unacceptable protocol version",
+        2 => "client id rejected",
+        3 => "server unavailable",
+        4 => "bad username or password",
+        5 => "not authorized",
+        _ => "unknown reason",
+    }
+}
+
+// the config of the room that gets drawn, None when it didn't come from
+// InfluxDB, which is the only place with any history
+fn newest_source<'a>(config: &'a Config, data: &Data) -> Option<&'a IndoorSourceConfig> {
+    if config.indoor_source != IndoorSourceKind::Influx {
+        return None;
+    }
+    let indoor = data.newest_indoor()?;
+    config
+        .indoor_sources
+        .iter()
+        .find(|source| source.name == indoor.label)
+}
+
+// Indoor temperature for the sparkline, empty when turned off or on failure
+fn get_indoor_history(agent: &ureq::Agent, config: &Config, data: &Data) -> Vec<f64> {
+    let source = match newest_source(config, data) {
+        Some(source) if config.history_hours > 0 => source,
+        _ => return Vec::new(),
+    };
+    match fetch_history(agent, config, source, "temperature", config.history_hours) {
+        Ok(history) => history
+            .into_iter()
+            .map(|t| config.units.convert_temp(t + config.indoor_temp_offset))
+            .collect(),
+        Err(e) => {
+            warn!("failed to get indoor history: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+// How the drawn room's pressure moved over the last PRESSURE_TREND_HOURS,
+// None when turned off, on failure or without enough samples to tell
+fn get_pressure_trend(agent: &ureq::Agent, config: &Config, data: &Data) -> Option<Trend> {
+    let source = newest_source(config, data).filter(|_| config.pressure_trend_hpa > 0.0)?;
+    match fetch_history(agent, config, source, "pressure", PRESSURE_TREND_HOURS) {
+        Ok(history) if history.len() >= 2 => {
+            let unit = config.indoor_pressure_unit;
+            Some(pressure_trend(
+                unit.to_hpa(history[history.len() - 1]),
+                unit.to_hpa(history[0]),
+                config.pressure_trend_hpa,
+            ))
+        }
+        Ok(_) => None,
+        Err(e) => {
+            warn!("failed to get the pressure trend: {}", e);
+            None
+        }
+    }
+}
+
+// a pressure tendency is usually given over 3 hours
+const PRESSURE_TREND_HOURS: u32 = 3;
+
+// steady unless it moved by more than threshold either way
+fn pressure_trend(now: f64, past: f64, threshold: f64) -> Trend {
+    let delta = now - past;
+    if delta > threshold {
+        Trend::Rising
+    } else if delta < -threshold {
+        Trend::Falling
+    } else {
+        Trend::Steady
+    }
+}
+
+// One value per influx_window over the last `hours`, oldest first. Windows
+// without any samples are left out rather than filled.
+fn fetch_history(
+    agent: &ureq::Agent,
+    config: &Config,
+    source: &IndoorSourceConfig,
+    field: &str,
+    hours: u32,
+) -> Result<Vec<f64>> {
+    let dialect = influx_dialect(config);
+    let query = dialect.history_query(config, source, field, hours);
+    let body = dialect.send(agent, config, &query)?;
+    dialect.parse_history(&body)
+}
+
+// The newest window's readings, whichever dialect they came from
+struct IndoorRow {
+    sampled_at: Option<DateTime<Utc>>,
+    temp: Option<f64>,
+    pressure: Option<f64>,
+    humidity: Option<f64>,
+    co2: Option<f64>,
+    voc: Option<f64>,
+}
+
+impl IndoorRow {
+    // both dialects hand the fields back by name, ones that weren't asked for
+    // are None
+    fn from_fields(
+        sampled_at: Option<DateTime<Utc>>,
+        field: impl Fn(&str) -> Option<f64>,
+    ) -> IndoorRow {
+        IndoorRow {
+            sampled_at,
+            temp: field("temperature"),
+            pressure: field("pressure"),
+            humidity: field("humidity"),
+            co2: field("co2"),
+            voc: field("voc"),
+        }
+    }
+
+    fn calibrated(self, config: &Config) -> IndoorRow {
+        IndoorRow {
+            temp: self.temp.map(|t| t + config.indoor_temp_offset),
+            pressure: self
+                .pressure
+                .map(|p| config.indoor_pressure_unit.to_hpa(p) + config.indoor_pressure_offset),
+            humidity: self
+                .humidity
+                .map(|h| (h + config.indoor_humidity_offset).clamp(0.0, 100.0)),
+            ..self
+        }
+    }
+}
+
+// the fields the newest readings are read from
+fn indoor_fields(config: &Config) -> Vec<&'static str> {
+    let mut fields = vec!["temperature", "pressure", "humidity"];
+    if config.indoor_co2 {
+        fields.push("co2");
+    }
+    if config.indoor_voc {
+        fields.push("voc");
+    }
+    fields
+}
+
+// InfluxQL for 1.x, or Flux for 2.x with influx_flux set. Each builds its own
+// queries and makes sense of its own response so the rest doesn't care which.
+// influx_window was checked by Config::validate before it gets in here.
+trait InfluxDialect {
+    // each indoor field over the newest influx_window, aggregated as configured
+    fn latest_query(&self, config: &Config, source: &IndoorSourceConfig) -> String;
+    // `field` per influx_window over the last `hours`, aggregated the same way
+    fn history_query(
+        &self,
+        config: &Config,
+        source: &IndoorSourceConfig,
+        field: &str,
+        hours: u32,
+    ) -> String;
+    fn send(&self, agent: &ureq::Agent, config: &Config, query: &str) -> Result<String>;
+    fn parse_latest(&self, body: &str) -> Result<Option<IndoorRow>>;
+    // oldest first, from a history_query response
+    fn parse_history(&self, body: &str) -> Result<Vec<f64>>;
+}
+
+fn influx_dialect(config: &Config) -> &'static dyn InfluxDialect {
+    if config.influx_flux {
+        &Flux
+    } else {
+        &InfluxQl
+    }
+}
+
+struct InfluxQl;
+
+impl InfluxQl {
+    // The one query's result. A query InfluxDB couldn't run, like one naming
+    // a database that doesn't exist, still gets a 200 with an error in place
+    // of the series, which would otherwise read as no data at all.
+    fn result(body: &str) -> Result<serde_json::Value> {
+        let response: serde_json::Value = serde_json::from_str(body)?;
+        let result = response["results"][0].clone();
+        match result["error"].as_str().or(response["error"].as_str()) {
+            Some(error) => Err(Oops::Data(format!("InfluxDB query failed: {}", error))),
+            None => Ok(result),
+        }
+    }
+}
+
+impl InfluxDialect for InfluxQl {
+    fn latest_query(&self, config: &Config, source: &IndoorSourceConfig) -> String {
+        let columns: Vec<String> = indoor_fields(config)
+            .into_iter()
+            .map(|field| {
+                let function = config.aggregation.of(field).function().to_uppercase();
+                format!("{1}({0}) as {0}", quote_ident(field), function)
+            })
+            .collect();
+        format!(
+            "SELECT {} FROM {}{} group by time({}) order by time desc limit 1",
+            columns.join(", "),
+            quote_ident(&source.measurement),
+            where_clause(&source.conditions()),
+            config.influx_window
+        )
+    }
+
+    fn history_query(
+        &self,
+        config: &Config,
+        source: &IndoorSourceConfig,
+        field: &str,
+        hours: u32,
+    ) -> String {
+        let mut conditions = source.conditions();
+        conditions.push(format!("time > now() - {}h", hours));
+        format!(
+            "SELECT {}({}) FROM {}{} group by time({}) fill(none) order by time asc",
+            config.aggregation.of(field).function().to_uppercase(),
+            quote_ident(field),
+            quote_ident(&source.measurement),
+            where_clause(&conditions),
+            config.influx_window
+        )
+    }
+
+    fn send(&self, agent: &ureq::Agent, config: &Config, query: &str) -> Result<String> {
+        let request = agent
+            .get(&config.influx_server)
+            .query("db", &config.influx_database)
+            .query("q", query);
+        let request = influx_auth(request, config);
+        Ok(call_with_retry("InfluxDB", request, config.retries)?.into_string()?)
+    }
+
+    // a row has a value for each of the series' columns, time first, any of
+    // which can be null
+    fn parse_latest(&self, body: &str) -> Result<Option<IndoorRow>> {
+        let response = InfluxQl::result(body)?;
+        let series = &response["series"][0];
+        let values = match series["values"][0].as_array() {
+            Some(values) => values,
+            None => return Ok(None),
+        };
+        let column = |name: &str| {
+            let columns = series["columns"].as_array()?;
+            let i = columns.iter().position(|column| column == name)?;
+            values.get(i)
+        };
+        let sampled_at = column("time")
+            .and_then(|t| t.as_str())
+            .and_then(parse_influx_time);
+        Ok(Some(IndoorRow::from_fields(sampled_at, |name| {
+            column(name)?.as_f64()
+        })))
+    }
+
+    fn parse_history(&self, body: &str) -> Result<Vec<f64>> {
+        let response = InfluxQl::result(body)?;
+        let values = response["series"][0]["values"]
+            .as_array()
+            .map_or(&[][..], |values| values.as_slice());
+        Ok(values.iter().filter_map(|row| row[1].as_f64()).collect())
+    }
+}
+
+struct Flux;
+
+impl Flux {
+    // the start of every query, narrowed down to the source's series
+    fn from_source(config: &Config, source: &IndoorSourceConfig, start: &str) -> String {
+        let mut predicate = format!("r._measurement == {}", flux_string(&source.measurement));
+        for (tag, value) in &source.tags {
+            predicate += &format!(" and r[{}] == {}", flux_string(tag), flux_string(value));
+        }
+        format!(
+            "from(bucket: {})\n  |> range(start: {})\n  |> filter(fn: (r) => {})\n",
+            flux_string(&config.influx_database),
+            start,
+            predicate
+        )
+    }
+}
+
+impl InfluxDialect for Flux {
+    // Flux needs a range, a day back is plenty to find the newest window since
+    // anything older is long stale. Grouping by field means every matching
+    // series gets aggregated together like InfluxQL does, and the windows are
+    // timed by their start to match it too. aggregateWindow takes one
+    // function, so fields aggregated differently get their own streams that
+    // are unioned back together.
+    fn latest_query(&self, config: &Config, source: &IndoorSourceConfig) -> String {
+        let mut by_function: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+        for field in indoor_fields(config) {
+            by_function
+                .entry(config.aggregation.of(field).function())
+                .or_default()
+                .push(format!("r._field == {}", flux_string(field)));
+        }
+        let streams: Vec<String> = by_function
+            .iter()
+            .map(|(function, fields)| {
+                format!(
+                    "{}  |> filter(fn: (r) => {})\n  |> group(columns: [\"_field\"])\n  |> aggregateWindow(every: {}, fn: {}, createEmpty: false, timeSrc: \"_start\")\n  |> last()\n",
+                    Flux::from_source(config, source, "-1d"),
+                    fields.join(" or "),
+                    config.influx_window,
+                    function
+                )
+            })
+            .collect();
+        let tables = match streams.as_slice() {
+            [stream] => stream.clone(),
+            _ => {
+                let streams: Vec<&str> = streams.iter().map(|stream| stream.trim_end()).collect();
+                format!("union(tables: [\n{}\n])\n", streams.join(",\n"))
+            }
+        };
+        format!(
+            "{}  |> pivot(rowKey: [\"_time\"], columnKey: [\"_field\"], valueColumn: \"_value\")\n",
+            tables
+        )
+    }
+
+    fn history_query(
+        &self,
+        config: &Config,
+        source: &IndoorSourceConfig,
+        field: &str,
+        hours: u32,
+    ) -> String {
+        format!(
+            "{}  |> filter(fn: (r) => r._field == {})\n  |> group()\n  |> aggregateWindow(every: {}, fn: {}, createEmpty: false, timeSrc: \"_start\")\n  |> keep(columns: [\"_time\", \"_value\"])\n",
+            Flux::from_source(config, source, &format!("-{}h", hours)),
+            flux_string(field),
+            config.influx_window,
+            config.aggregation.of(field).function()
+        )
+    }
+
+    fn send(&self, agent: &ureq::Agent, config: &Config, query: &str) -> Result<String> {
+        // validate makes sure there's an org when influx_flux is set
+        let org = config.influx_org.as_deref().unwrap_or_default();
+        let request = agent
+            .post(&config.influx_server)
+            .query("org", org)
+            .set("Content-Type", "application/vnd.flux")
+            .set("Accept", "application/csv");
+        let request = influx_auth(request, config);
+        Ok(send_with_retry("InfluxDB", request, Some(query), config.retries)?.into_string()?)
+    }
+
+    // when the last windows of the fields don't line up the pivot leaves a row
+    // for each, the newest wins
+    fn parse_latest(&self, body: &str) -> Result<Option<IndoorRow>> {
+        let field = |record: &BTreeMap<String, String>, name: &str| {
+            record.get(name).and_then(|value| value.parse().ok())
+        };
+        let row = parse_flux_csv(body)?
+            .iter()
+            .map(|record| {
+                let sampled_at = record.get("_time").and_then(|t| parse_influx_time(t));
+                IndoorRow::from_fields(sampled_at, |name| field(record, name))
+            })
+            .max_by_key(|row| row.sampled_at);
+        Ok(row)
+    }
+
+    fn parse_history(&self, body: &str) -> Result<Vec<f64>> {
+        Ok(parse_flux_csv(body)?
+            .iter()
+            .filter_map(|record| record.get("_value")?.parse().ok())
+            .collect())
+    }
+}
+
+// Flux strings go in double quotes with backslash escapes, and ${ would
+// start an interpolation
+fn flux_string(value: &str) -> String {
+    format!(
+        "\"{}\"",
+        value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace("${", "\\${")
+    )
+}
+
+// Annotated CSV as returned by /api/v2/query: every table starts with a
+// header row, optionally after #annotation rows, and tables are separated by
+// blank lines. A failed query can still come back as a 200 with a table
+// holding an error column.
+fn parse_flux_csv(body: &str) -> Result<Vec<BTreeMap<String, String>>> {
+    let mut records = Vec::new();
+    let mut header: Option<Vec<String>> = None;
+    for line in body.lines().map(|line| line.trim_end_matches('\r')) {
+        if line.is_empty() {
+            header = None;
+            continue;
+        }
+        if line.starts_with('#') {
+            continue;
+        }
+        let fields = split_csv_line(line);
+        let columns = match &header {
+            Some(columns) => columns,
+            None => {
+                header = Some(fields);
+                continue;
+            }
+        };
+        let record: BTreeMap<String, String> = columns.iter().cloned().zip(fields).collect();
+        if let Some(e) = record.get("error").filter(|e| !e.is_empty()) {
+            return Err(Oops::Data(format!("InfluxDB query failed: {}", e)));
+        }
+        records.push(record);
+    }
+    Ok(records)
+}
+
+// splits on commas outside of double quotes, where "" is a literal quote
+fn split_csv_line(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+fn parse_influx_time(time: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(time)
+        .ok()
+        .map(|t| t.with_timezone(&Utc))
+}
+
+// Adds whatever auth is configured to a request for influx_server
+fn influx_auth(request: ureq::Request, config: &Config) -> ureq::Request {
+    match (&config.influx_token, &config.influx_user) {
+        (Some(token), _) => request.set("Authorization", &format!("Token {}", token)),
+        (None, Some(user)) => {
+            let password = config.influx_password.as_deref().unwrap_or("");
+            let credentials = base64::encode(format!("{}:{}", user, password));
+            request.set("Authorization", &format!("Basic {}", credentials))
+        }
+        (None, None) => request,
+    }
+}
+
+fn indoor_data(label: &str, row: IndoorRow, units: Units) -> IndoorData {
+    let dewpoint = match (row.temp, row.humidity) {
+        (Some(temp), Some(humidity)) if humidity > 0.0 => Some(dewpoint(temp, humidity)),
+        _ => None,
+    };
+    IndoorData {
+        label: label.to_string(),
+        sampled_at: row.sampled_at,
+        temp: row.temp.map(|t| units.convert_temp(t)),
+        humidity: row.humidity,
+        pressure: row.pressure.map(|p| units.convert_pressure(p)),
+        dewpoint: dewpoint.map(|t| units.convert_temp(t)),
+        co2: row.co2,
+        voc: row.voc,
+    }
+}
+
+// Magnus formula with the Sonntag constants, good to about 0.1C between
+// -45C and 60C. rh is in percent and has to be above 0.
+fn dewpoint(temp_c: f64, rh: f64) -> f64 {
+    const A: f64 = 17.62;
+    const B: f64 = 243.12;
+    let gamma = (rh / 100.0).ln() + A * temp_c / (B + temp_c);
+    B * gamma / (A - gamma)
+}
+
+// InfluxQL duration literals, one or more <integer><unit> pairs like 1h30m
+fn is_influx_duration(s: &str) -> bool {
+    const UNITS: [&str; 11] = ["ns", "us", "µs", "ms", "u", "µ", "s", "m", "h", "d", "w"];
+    let mut rest = s;
+    if rest.is_empty() {
+        return false;
+    }
+    while !rest.is_empty() {
+        let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        if digits == 0 {
+            return false;
+        }
+        rest = &rest[digits..];
+        match UNITS.iter().find(|unit| rest.starts_with(*unit)) {
+            Some(unit) => rest = &rest[unit.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+/// Outdoor and forecast come out of the same onecall response, so it's fetched
+/// on first use and shared between them
+pub struct OpenWeather<'a> {
+    config: &'a Config,
+    agent: &'a ureq::Agent,
+    // when the run started, which picks the location and the days shown
+    now: DateTime<Local>,
+    // today's, from Config::location
+    location: Location,
+    // where the last response and the call count are kept, cache_dir()
+    cache_dir: PathBuf,
+    response: OnceCell<serde_json::Value>,
+}
+
+impl<'a> OpenWeather<'a> {
+    pub fn new(
+        config: &'a Config,
+        agent: &'a ureq::Agent,
+        now: DateTime<Local>,
+    ) -> OpenWeather<'a> {
+        OpenWeather {
+            config,
+            agent,
+            now,
+            location: config.location(now.weekday()),
+            cache_dir: cache_dir(),
+            response: OnceCell::new(),
+        }
+    }
+
+    fn response(&self) -> Result<&serde_json::Value> {
+        if let Some(response) = self.response.get() {
+            return Ok(response);
+        }
+        let config = self.config;
+        let url = config
+            .openweather_api_version
+            .onecall_url(&config.openweather_base_url);
+        let request = self
+            .agent
+            .get(&url)
+            .query("lat", &self.location.lat.to_string())
+            .query("lon", &self.location.lon.to_string())
+            .query("appid", &config.openweather_api_key)
+            .query("units", config.units.api_name());
+        // daily is only read for the forecast
+        let mut exclude = config.openweather_exclude.clone();
+        if !config.show_forecast {
+            exclude.push("daily".to_string());
+        }
+        let request = if exclude.is_empty() {
+            request
+        } else {
+            request.query("exclude", &exclude.join(","))
+        };
+        let fetched = self
+            .count_call()
+            .and_then(|()| call_with_retry("OpenWeather", request, config.retries))
+            .and_then(|response| Ok(response.into_json()?));
+        let response = match fetched {
+            Ok(response) => {
+                warn_on_clock_skew(&response);
+                let now = self.now.with_timezone(&Utc);
+                if let Err(e) =
+                    save_cached_weather(&self.cache_path(), &self.location, &response, now)
+                {
+                    warn!("failed to cache weather response: {}", e);
+                }
+                response
+            }
+            Err(e) => {
+                let max_age = chrono::Duration::minutes(config.weather_max_age_minutes);
+                let now = self.now.with_timezone(&Utc);
+                match load_cached_weather(&self.cache_path(), &self.location, max_age, now) {
+                    Some(cached) => {
+                        warn!("weather fetch failed, using cached response: {}", e);
+                        cached
+                    }
+                    None => return Err(e),
+                }
+            }
+        };
+        Ok(self.response.get_or_init(|| response))
+    }
+
+    fn cache_path(&self) -> PathBuf {
+        self.cache_dir.join("weather.json")
+    }
+
+    // Counts a call against openweather_daily_cap, or fails without counting
+    // it once the cap is reached
+    fn count_call(&self) -> Result<()> {
+        let cap = match self.config.openweather_daily_cap {
+            Some(cap) => cap,
+            None => return Ok(()),
+        };
+        let path = self.cache_dir.join("openweather_calls.json");
+        let today = self.now.with_timezone(&Utc).date().naive_utc();
+        if !under_rate_limit(&path, cap, today) {
+            return Err(Oops::Data(format!(
+                "OpenWeather's daily cap of {} calls is used up",
+                cap
+            )));
+        }
+        if let Err(e) = save_call_count(&path, today, calls_on(&path, today) + 1) {
+            warn!("failed to count the OpenWeather call: {}", e);
+        }
+        Ok(())
+    }
+
+    // A separate api from the onecall one, so a failure here only loses the
+    // index. It isn't cached since a stale index isn't worth showing.
+    fn air_quality(&self) -> Option<u8> {
+        let config = self.config;
+        let request = self
+            .agent
+            .get(&format!(
+                "{}/data/2.5/air_pollution",
+                config.openweather_base_url.trim_end_matches('/')
+            ))
+            .query("lat", &self.location.lat.to_string())
+            .query("lon", &self.location.lon.to_string())
+            .query("appid", &config.openweather_api_key);
+        let response: Result<serde_json::Value> = self
+            .count_call()
+            .and_then(|()| call_with_retry("OpenWeather air pollution", request, config.retries))
+            .and_then(|response| Ok(response.into_json()?));
+        match response {
+            Ok(response) => response["list"][0]["main"]["aqi"]
+                .as_u64()
+                .filter(|aqi| (1..=5).contains(aqi))
+                .map(|aqi| aqi as u8),
+            Err(e) => {
+                warn!("failed to get air quality: {}", e);
+                None
+            }
+        }
+    }
+}
+
+// The WHO's bands, for the index rounded the way it's drawn
+fn uvi_band(uvi: f64) -> &'static str {
+    match uvi.round() as i64 {
+        i64::MIN..=2 => "Low",
+        3..=5 => "Moderate",
+        6..=7 => "High",
+        8..=10 => "Very High",
+        _ => "Extreme",
+    }
+}
+
+// {:.0} would round 2.5 to even and draw UV 2 Moderate
+fn uvi_text(uvi: f64) -> String {
+    format!("UV {} {}", uvi.round(), uvi_band(uvi))
+}
+
+// OpenWeather's names for each step of the index
+fn aqi_label(aqi: u8) -> &'static str {
+    match aqi {
+        1 => "Good",
+        2 => "Fair",
+        3 => "Moderate",
+        4 => "Poor",
+        _ => "Very Poor",
+    }
+}
+
+impl WeatherSource for OpenWeather<'_> {
+    fn outdoor(&self) -> Result<OutdoorData> {
+        let response = self.response()?;
+        let offset = response["timezone_offset"]
+            .as_i64()
+            .and_then(|o| FixedOffset::east_opt(o as i32))
+            .unwrap_or_else(|| FixedOffset::east(0));
+        let at = |field: &str| {
+            let timestamp = response["current"][field].as_i64()?;
+            offset.timestamp_opt(timestamp, 0).single()
+        };
+        Ok(OutdoorData {
+            temp: response["current"][self.config.outdoor_temp_source.field()].as_f64(),
+            humidity: response["current"]["humidity"].as_f64(),
+            pressure: response["current"]["pressure"]
+                .as_f64()
+                .map(|p| self.config.units.convert_pressure(p)),
+            sunrise: at("sunrise"),
+            sunset: at("sunset"),
+            aqi: if self.config.air_quality {
+                self.air_quality()
+            } else {
+                None
+            },
+            uvi: response["current"]["uvi"].as_f64(),
+        })
+    }
+
+    fn forecast(&self) -> Result<ForecastData> {
+        let response = self.response()?;
+        let offset = response["timezone_offset"].as_i64().unwrap_or(0);
+        let daily = response["daily"]
+            .as_array()
+            .map_or(&[][..], |d| d.as_slice());
+        // daily starts with the location's today when it's fetched, but a
+        // cached response or one fetched just before midnight there can start
+        // on a day that's already over
+        let today = (self.now.naive_utc() + chrono::Duration::seconds(offset)).date();
+        let date = |day: &serde_json::Value| {
+            day["dt"]
+                .as_i64()
+                .and_then(|dt| NaiveDateTime::from_timestamp_opt(dt + offset, 0))
+                .map(|t| t.date())
+        };
+        let first = daily
+            .iter()
+            .position(|day| date(day).is_none_or(|date| date >= today))
+            .unwrap_or(daily.len());
+        // a high or low of 0 would pass for a real one, so those have to be
+        // there, the rest just look off when they're missing
+        let mut days = (first..daily.len().min(first + FORECAST_DAYS))
+            .map(|i| {
+                let day = &daily[i];
+                let i = i.to_string();
+                Ok(DayForecast {
+                    date: date(day),
+                    high: get_f64(response, &["daily", &i, "temp", "max"])?,
+                    low: get_f64(response, &["daily", &i, "temp", "min"])?,
+                    condition: day["weather"][0]["main"].as_str().unwrap_or("").to_string(),
+                    condition_id: day["weather"][0]["id"].as_u64().map(|id| id as u32),
+                    pop: day["pop"].as_f64().unwrap_or(0.0),
+                    wind_speed: day["wind_speed"].as_f64().unwrap_or(0.0),
+                    wind_deg: day["wind_deg"].as_f64().unwrap_or(0.0),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if self.config.remaining_day_high_low {
+            let hourly = response["hourly"]
+                .as_array()
+                .map_or(&[][..], |h| h.as_slice());
+            if let (Some(day), Some((low, high))) = (
+                days.first_mut().filter(|day| day.date == Some(today)),
+                remaining_day_range(hourly, offset, self.now.timestamp()),
+            ) {
+                day.low = low;
+                day.high = high;
+            }
+        }
+        // the phase of the first day that's left
+        let moon_phase = daily.get(first).and_then(|day| day["moon_phase"].as_f64());
+        Ok(ForecastData { days, moon_phase })
+    }
+}
+
+// The low and high of the hourly temps from the hour `now` is in to the end
+// of that day at the location, `offset` seconds from UTC. None without any,
+// like when hourly was excluded.
+fn remaining_day_range(hourly: &[serde_json::Value], offset: i64, now: i64) -> Option<(f64, f64)> {
+    let date =
+        |timestamp: i64| NaiveDateTime::from_timestamp_opt(timestamp + offset, 0).map(|t| t.date());
+    let today = date(now)?;
+    hourly
+        .iter()
+        .filter_map(|hour| {
+            let dt = hour["dt"].as_i64()?;
+            // each entry is the hour starting at dt
+            if dt + 3600 <= now || date(dt)? != today {
+                return None;
+            }
+            hour["temp"].as_f64()
+        })
+        .fold(None, |range, temp| match range {
+            None => Some((temp, temp)),
+            Some((low, high)) => Some((temp.min(low), temp.max(high))),
+        })
+}
+
+// The number at path, where a segment that's all digits indexes into an
+// array. Indexing a Value straight gives Null for anything missing, which
+// hides which part of the response changed shape, so the error names it.
+fn get_f64(value: &serde_json::Value, path: &[&str]) -> Result<f64> {
+    let mut current = value;
+    for (i, segment) in path.iter().enumerate() {
+        let next = match segment.parse::<usize>() {
+            Ok(index) => current.get(index),
+            Err(_) => current.get(segment),
+        };
+        current = next.ok_or_else(|| {
+            Oops::Data(format!(
+                "{} missing from the response",
+                path[..=i].join(".")
+            ))
+        })?;
+    }
+    current
+        .as_f64()
+        .ok_or_else(|| Oops::Data(format!("{} in the response isn't a number", path.join("."))))
+}
+
+fn weather_source<'a>(
+    config: &'a Config,
+    agent: &'a ureq::Agent,
+    now: DateTime<Local>,
+) -> Box<dyn WeatherSource + 'a> {
+    match config.weather_provider {
+        WeatherProvider::OpenWeather => Box::new(OpenWeather::new(config, agent, now)),
+        WeatherProvider::OpenMeteo => Box::new(OpenMeteo::new(config, agent, now)),
+    }
+}
+
+/// Open-Meteo's forecast api, which needs no key. Like OpenWeather outdoor and
+/// forecast come out of one response, fetched on first use. There's no cached
+/// response to fall back on, the cache is kept in OpenWeather's shape.
+pub struct OpenMeteo<'a> {
+    config: &'a Config,
+    agent: &'a ureq::Agent,
+    now: DateTime<Local>,
+    location: Location,
+    response: OnceCell<serde_json::Value>,
+}
+
+// the current and daily variables read, daily ones are arrays with an entry
+// per day in daily.time
+const OPEN_METEO_CURRENT: &str =
+    "temperature_2m,apparent_temperature,relative_humidity_2m,pressure_msl,uv_index";
+const OPEN_METEO_DAILY: &str = "weather_code,temperature_2m_max,temperature_2m_min,precipitation_probability_max,wind_speed_10m_max,wind_direction_10m_dominant,sunrise,sunset";
+
+impl<'a> OpenMeteo<'a> {
+    pub fn new(config: &'a Config, agent: &'a ureq::Agent, now: DateTime<Local>) -> OpenMeteo<'a> {
+        OpenMeteo {
+            config,
+            agent,
+            now,
+            location: config.location(now.weekday()),
+            response: OnceCell::new(),
+        }
+    }
+
+    // With timezone=auto the days and times are the location's local ones,
+    // utc_offset_seconds says how far that is from UTC. A day more than shown
+    // is asked for in case the first one is already over.
+    fn response(&self) -> Result<&serde_json::Value> {
+        if let Some(response) = self.response.get() {
+            return Ok(response);
+        }
+        let config = self.config;
+        let (temperature_unit, wind_speed_unit) = config.units.open_meteo_units();
+        let url = format!(
+            "{}/v1/forecast",
+            config.open_meteo_base_url.trim_end_matches('/')
+        );
+        let request = self
+            .agent
+            .get(&url)
+            .query("latitude", &self.location.lat.to_string())
+            .query("longitude", &self.location.lon.to_string())
+            .query("current", OPEN_METEO_CURRENT)
+            .query("daily", OPEN_METEO_DAILY)
+            .query("timezone", "auto")
+            .query("forecast_days", &(FORECAST_DAYS + 1).to_string())
+            .query("temperature_unit", temperature_unit)
+            .query("wind_speed_unit", wind_speed_unit);
+        let response = call_with_retry("Open-Meteo", request, config.retries)?.into_json()?;
+        Ok(self.response.get_or_init(|| response))
+    }
+
+    fn offset(response: &serde_json::Value) -> FixedOffset {
+        response["utc_offset_seconds"]
+            .as_i64()
+            .and_then(|o| FixedOffset::east_opt(o as i32))
+            .unwrap_or_else(|| FixedOffset::east(0))
+    }
+
+    // the location's today, which daily should start with
+    fn today(&self, response: &serde_json::Value) -> NaiveDate {
+        self.now
+            .with_timezone(&OpenMeteo::offset(response))
+            .date()
+            .naive_local()
+    }
+}
+
+// Open-Meteo's local times, like 2026-10-14T07:12, without an offset
+fn open_meteo_time(offset: FixedOffset, time: &serde_json::Value) -> Option<DateTime<FixedOffset>> {
+    let time = NaiveDateTime::parse_from_str(time.as_str()?, "%Y-%m-%dT%H:%M").ok()?;
+    offset.from_local_datetime(&time).single()
+}
+
+// The OpenWeather condition closest to a WMO weather code, so Open-Meteo days
+// get the same icons and names
+fn wmo_condition(code: u64) -> Option<(&'static str, u32)> {
+    let condition = match code {
+        0 => ("Clear", 800),
+        1 => ("Clouds", 801),
+        2 => ("Clouds", 802),
+        3 => ("Clouds", 804),
+        45 | 48 => ("Fog", 741),
+        51..=55 => ("Drizzle", 301),
+        // freezing drizzle and rain
+        56 | 57 | 66 | 67 => ("Rain", 511),
+        61..=65 => ("Rain", 501),
+        71..=75 => ("Snow", 601),
+        77 => ("Snow", 600),
+        80..=82 => ("Rain", 521),
+        85 | 86 => ("Snow", 621),
+        95 => ("Thunderstorm", 211),
+        96 | 99 => ("Thunderstorm", 202),
+        _ => return None,
+    };
+    Some(condition)
+}
+
+impl WeatherSource for OpenMeteo<'_> {
+    // the sun times are today's daily entry, the first one unless it's found
+    fn outdoor(&self) -> Result<OutdoorData> {
+        let response = self.response()?;
+        let offset = OpenMeteo::offset(response);
+        let current = &response["current"];
+        let today = self.today(response).format("%Y-%m-%d").to_string();
+        let daily = &response["daily"];
+        let i = daily["time"]
+            .as_array()
+            .and_then(|dates| dates.iter().position(|date| *date == *today))
+            .unwrap_or(0);
+        let temp_field = match self.config.outdoor_temp_source {
+            OutdoorTempSource::FeelsLike => "apparent_temperature",
+            OutdoorTempSource::Temp => "temperature_2m",
+        };
+        Ok(OutdoorData {
+            temp: current[temp_field].as_f64(),
+            humidity: current["relative_humidity_2m"].as_f64(),
+            pressure: current["pressure_msl"]
+                .as_f64()
+                .map(|p| self.config.units.convert_pressure(p)),
+            sunrise: open_meteo_time(offset, &daily["sunrise"][i]),
+            sunset: open_meteo_time(offset, &daily["sunset"][i]),
+            aqi: None,
+            uvi: current["uv_index"].as_f64(),
+        })
+    }
+
+    fn forecast(&self) -> Result<ForecastData> {
+        let response = self.response()?;
+        let today = self.today(response);
+        let daily = &response["daily"];
+        let dates = daily["time"].as_array().map_or(&[][..], |d| d.as_slice());
+        let value = |name: &str, i: usize| daily[name][i].as_f64();
+        let date = |date: &serde_json::Value| {
+            date.as_str()
+                .and_then(|d| NaiveDate::parse_from_str(d, "%Y-%m-%d").ok())
+        };
+        let first = dates
+            .iter()
+            .position(|d| date(d).is_none_or(|date| date >= today))
+            .unwrap_or(dates.len());
+        // the highs and lows have to be there, as for OpenWeather
+        let days = (first..dates.len().min(first + FORECAST_DAYS))
+            .map(|i| {
+                let condition = daily["weather_code"][i].as_u64().and_then(wmo_condition);
+                let index = i.to_string();
+                Ok(DayForecast {
+                    date: date(&dates[i]),
+                    high: get_f64(response, &["daily", "temperature_2m_max", &index])?,
+                    low: get_f64(response, &["daily", "temperature_2m_min", &index])?,
+                    condition: condition.map_or("", |(name, _)| name).to_string(),
+                    condition_id: condition.map(|(_, id)| id),
+                    // a percentage rather than OpenWeather's 0 to 1
+                    pop: value("precipitation_probability_max", i).unwrap_or(0.0) / 100.0,
+                    wind_speed: value("wind_speed_10m_max", i).unwrap_or(0.0),
+                    wind_deg: value("wind_direction_10m_dominant", i).unwrap_or(0.0),
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(ForecastData {
+            days,
+            moon_phase: None,
+        })
+    }
+}
+
+/// Shared by every source so they all get the configured timeouts
+pub fn http_agent(config: &Config) -> ureq::Agent {
+    agent_builder(config).build()
+}
+
+fn agent_builder(config: &Config) -> ureq::AgentBuilder {
+    ureq::AgentBuilder::new()
+        .timeout_connect(Duration::from_secs(config.timeout_connect_secs))
+        .timeout_read(Duration::from_secs(config.timeout_read_secs))
+}
+
+/// For InfluxDB, which can be behind a private CA or want a client
+/// certificate: like http_agent but also trusting influx_ca_file, and
+/// presenting influx_client_cert when they're set
+pub fn influx_agent(config: &Config) -> Result<ureq::Agent> {
+    if config.influx_ca_file.is_none() && config.influx_client_cert.is_none() {
+        return Ok(http_agent(config));
+    }
+
+    // the usual roots still, a private CA is on top of them
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|ta| {
+        rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
+            ta.subject,
+            ta.spki,
+            ta.name_constraints,
+        )
+    }));
+    if let Some(path) = &config.influx_ca_file {
+        for der in read_pem(path, |label| label == "CERTIFICATE")? {
+            roots.add(&rustls::Certificate(der)).map_err(|e| {
+                Oops::Config(format!(
+                    "influx_ca_file {} has a bad certificate: {:?}",
+                    path.display(),
+                    e
+                ))
+            })?;
+        }
+    }
+
+    let builder = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots);
+    // validate makes sure the cert and key come together
+    let tls = match (&config.influx_client_cert, &config.influx_client_key) {
+        (Some(cert_path), Some(key_path)) => {
+            let chain = read_pem(cert_path, |label| label == "CERTIFICATE")?
+                .into_iter()
+                .map(rustls::Certificate)
+                .collect();
+            let key = read_pem(key_path, |label| label.ends_with("PRIVATE KEY"))?
+                .into_iter()
+                .next()
+                .map(rustls::PrivateKey)
+                .ok_or_else(|| {
+                    Oops::Config(format!(
+                        "influx_client_key {} has no private key",
+                        key_path.display()
+                    ))
+                })?;
+            builder
+                .with_single_cert(chain, key)
+                .map_err(|e| Oops::Config(format!("influx_client_cert: {}", e)))?
+        }
+        _ => builder.with_no_client_auth(),
+    };
+    Ok(agent_builder(config).tls_config(Arc::new(tls)).build())
+}
+
+// The DER of each block in a PEM file whose label (CERTIFICATE in
+// -----BEGIN CERTIFICATE-----) is wanted
+fn read_pem(path: &Path, wanted: impl Fn(&str) -> bool) -> Result<Vec<Vec<u8>>> {
+    let bad = |problem: String| Oops::Config(format!("{}: {}", path.display(), problem));
+    let pem = fs::read_to_string(path).map_err(|e| bad(e.to_string()))?;
+    let mut blocks = Vec::new();
+    let mut current: Option<(String, String)> = None;
+    for line in pem.lines().map(str::trim) {
+        if let Some(label) = line
+            .strip_prefix("-----BEGIN ")
+            .and_then(|rest| rest.strip_suffix("-----"))
+        {
+            current = Some((label.to_string(), String::new()));
+        } else if line.starts_with("-----END ") {
+            if let Some((label, base64)) = current.take() {
+                if wanted(&label) {
+                    blocks.push(base64::decode(&base64).map_err(|e| bad(e.to_string()))?);
+                }
+            }
+        } else if let Some((_, base64)) = &mut current {
+            base64.push_str(line);
+        }
+    }
+    if blocks.is_empty() {
+        return Err(bad("has nothing usable in it".to_string()));
+    }
+    Ok(blocks)
+}
+
+// Quiet hours run from start up to (not including) end, wrapping past midnight
+// when start is later than end
+fn is_quiet(hour: u32, start: u32, end: u32) -> bool {
+    if start <= end {
+        (start..end).contains(&hour)
+    } else {
+        hour >= start || hour < end
+    }
+}
+
+// Retries connection failures and 5xx responses with exponential backoff,
+// anything else (like a 4xx) is returned straight away
+fn call_with_retry(name: &str, request: ureq::Request, retries: u32) -> Result<ureq::Response> {
+    send_with_retry(name, request, None, retries)
+}
+
+// call_with_retry with a body, sending it again on every attempt
+fn send_with_retry(
+    name: &str,
+    request: ureq::Request,
+    body: Option<&str>,
+    retries: u32,
+) -> Result<ureq::Response> {
+    let mut delay = Duration::from_millis(500);
+    let mut attempt = 0;
+    loop {
+        let sent = match body {
+            Some(body) => request.clone().send_string(body),
+            None => request.clone().call(),
+        };
+        match sent {
+            Ok(response) => return Ok(response),
+            Err(e) if attempt < retries && is_transient(&e) => {
+                attempt += 1;
+                warn!(
+                    "{} failed: {}, retrying in {:?} ({}/{})",
+                    name, e, delay, attempt, retries
+                );
+                thread::sleep(delay);
+                delay *= 2;
+            }
+            Err(ureq::Error::Status(code, response)) => {
+                return Err(Oops::Status {
+                    service: name.to_string(),
+                    code,
+                    reason: response.status_text().to_string(),
+                })
+            }
+            Err(e) => {
+                if let Some(tls) = tls_error(&e) {
+                    return Err(Oops::Tls(format!("{}, {}", name, tls)));
+                }
+                return Err(e.into());
+            }
+        }
+    }
+}
+
+// ureq reports both connect and read timeouts as a transport error wrapping
+// an io::Error of kind TimedOut
+fn is_timeout(e: &ureq::Error) -> bool {
+    error::Error::source(e)
+        .and_then(|source| source.downcast_ref::<std::io::Error>())
+        .is_some_and(|e| e.kind() == std::io::ErrorKind::TimedOut)
+}
+
+// rustls handshake errors come back as a connection failure wrapping an
+// io::Error wrapping the rustls one
+fn tls_error(e: &ureq::Error) -> Option<&rustls::Error> {
+    error::Error::source(e)?
+        .downcast_ref::<std::io::Error>()?
+        .get_ref()?
+        .downcast_ref::<rustls::Error>()
+}
+
+// a TLS failure won't go away by trying again
+fn is_transient(e: &ureq::Error) -> bool {
+    if tls_error(e).is_some() {
+        return false;
+    }
+    match e {
+        ureq::Error::Status(code, _) => *code >= 500,
+        ureq::Error::Transport(_) => matches!(
+            e.kind(),
+            ureq::ErrorKind::Dns | ureq::ErrorKind::ConnectionFailed | ureq::ErrorKind::Io
+        ),
+    }
+}
+
+// formats a reading, or the placeholder if we don't have one
+fn format_reading(value: Option<f64>, format: impl Fn(f64) -> String) -> String {
+    value.map_or_else(|| PLACEHOLDER.to_string(), format)
+}
+
+// indoor minus outdoor, in whatever units they're both in
+fn temp_delta(indoor: Option<f64>, outdoor: Option<f64>) -> Option<f64> {
+    Some(indoor? - outdoor?)
+}
+
+// openweather gives the probability of precipitation as a fraction 0-1
+fn format_pop(pop: f64) -> String {
+    format!("{:.1}%", (pop * 100.0).clamp(0.0, 100.0))
+}
+
+// 8 point compass abbreviation, each point covers 22.5 degrees either side
+fn compass_point(deg: f64) -> &'static str {
+    const POINTS: [&str; 8] = ["N", "NE", "E", "SE", "S", "SW", "W", "NW"];
+    let index = (deg.rem_euclid(360.0) / 45.0).round() as usize % POINTS.len();
+    POINTS[index]
+}
+
+#[derive(Debug, PartialEq)]
+enum Comfort {
+    Low,
+    Ok,
+    High,
+}
+
+impl Comfort {
+    fn label(self) -> &'static str {
+        match self {
+            Comfort::Low => "LOW",
+            Comfort::Ok => "OK",
+            Comfort::High => "HIGH",
+        }
+    }
+}
+
+// low and high themselves still count as comfortable
+fn comfort_band(rh: f64, low: f64, high: f64) -> Comfort {
+    if rh < low {
+        Comfort::Low
+    } else if rh > high {
+        Comfort::High
+    } else {
+        Comfort::Ok
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum SunPhase {
+    BeforeSunrise,
+    Day,
+    AfterSunset,
+}
+
+fn sun_phase<Tz: TimeZone>(
+    now: DateTime<Tz>,
+    sunrise: DateTime<FixedOffset>,
+    sunset: DateTime<FixedOffset>,
+) -> SunPhase {
+    if now < sunrise {
+        SunPhase::BeforeSunrise
+    } else if now < sunset {
+        SunPhase::Day
+    } else {
+        SunPhase::AfterSunset
+    }
+}
+
+// The next sunrise or sunset, like "dusk 18:40"
+fn sun_text(outdoor: &OutdoorData, now: DateTime<Local>) -> Option<String> {
+    let (sunrise, sunset) = (outdoor.sunrise?, outdoor.sunset?);
+    Some(match sun_phase(now, sunrise, sunset) {
+        SunPhase::BeforeSunrise => format!("dawn {}", sunrise.format("%H:%M")),
+        SunPhase::Day => format!("dusk {}", sunset.format("%H:%M")),
+        // only today's times are known, tomorrow's sunrise is within a few
+        // minutes of today's
+        SunPhase::AfterSunset => format!("dawn {} tmrw", sunrise.format("%H:%M")),
+    })
+}
+
+// today's the first forecast day, the ones already over are dropped
+fn rain_expected(data: &Data, config: &Config) -> bool {
+    data.forecast
+        .as_ref()
+        .and_then(|forecast| forecast.days.first())
+        .is_some_and(|today| today.pop > config.rain_banner_threshold)
+}
+
+fn indoor_is_stale(data: &Data, config: &Config, now: DateTime<Utc>) -> bool {
+    let threshold = chrono::Duration::minutes(config.stale_after_minutes);
+    match data.newest_indoor().and_then(|d| d.sampled_at) {
+        Some(sampled_at) => is_stale(sampled_at, now, threshold),
+        None => false,
+    }
+}
+
+fn is_stale(sampled_at: DateTime<Utc>, now: DateTime<Utc>, threshold: chrono::Duration) -> bool {
+    now - sampled_at > threshold
+}
+
+/// Lays out `data` on the whole display, which should already be rotated to
+/// landscape and cleared to white
+pub fn draw(
+    display: &mut VarDisplay,
+    config: &Config,
+    data: &Data,
+    now: DateTime<Local>,
+) -> Result<()> {
+    let units = config.units;
+    let precision = &config.precision;
+    let (width, mut height) = canvas_size(display);
+
+    // with the panels squeezed up to make room
+    if rain_expected(data, config) {
+        draw_rain_banner(display)?;
+        height -= BANNER_HEIGHT;
+    }
+
+    // without the forecast there's nothing for its column
+    let layout = match config.layout {
+        LayoutChoice::ThreeColumn if config.show_forecast => {
+            three_column_layout(width, height, config)
+        }
+        LayoutChoice::ThreeColumn | LayoutChoice::TwoColumn => {
+            two_column_layout(width, height, config)
+        }
+    };
+    let (major_font, minor_font) = (layout.major_font, config.minor_font);
+    for &(start, end) in &layout.dividers {
+        draw_divider(display, config, start, end)?;
+    }
+
+    // left column indoor data
+    let left_top = layout.indoor_major;
+    let left_bottom = layout.indoor_minor;
+    let indoor = data.newest_indoor();
+    let temp_txt = format_reading(indoor.and_then(|d| d.temp), |t| {
+        units.format_temp(t, precision.temp)
+    });
+    draw_centered(display, &temp_txt, left_top, major_font)?;
+
+    if indoor_is_stale(data, config, now.with_timezone(&Utc)) {
+        draw_stale_marker(display)?;
+    }
+
+    // which room this is, between the stale marker and the battery
+    if let Some(indoor) = indoor.filter(|_| config.indoor_sources.len() > 1) {
+        let right_edge = left_top.bottom_right.x;
+        let label = Rectangle::new(Point::new(14, 2), Point::new(right_edge - 28, 10));
+        let label_text_style = TextBoxStyleBuilder::new(Font6x8)
+            .text_color(Black)
+            .alignment(CenterAligned)
+            .build();
+        TextBox::new(&indoor.label, label)
+            .into_styled(label_text_style)
+            .draw(display)?;
+    }
+
+    if let Some(battery) = &data.battery {
+        let battery_txt = format!("{:.0}%", battery.percent.clamp(0.0, 100.0));
+        let right_edge = left_top.bottom_right.x;
+        let corner = Rectangle::new(
+            Point::new(right_edge - 26, 2),
+            Point::new(right_edge - 2, 10),
+        );
+        let battery_text_style = TextBoxStyleBuilder::new(Font6x8)
+            .text_color(Black)
+            .alignment(RightAligned)
+            .build();
+        TextBox::new(&battery_txt, corner)
+            .into_styled(battery_text_style)
+            .draw(display)?;
+    }
+
+    // trend along the bottom edge, under the reading
+    let spark = Rectangle::new(
+        Point::new(left_top.top_left.x + 6, left_top.bottom_right.y - 14),
+        Point::new(left_top.bottom_right.x - 6, left_top.bottom_right.y - 4),
+    );
+    draw_sparkline(display, &data.indoor_history, spark)?;
+
+    let humidity_txt = format_reading(indoor.and_then(|d| d.humidity), |h| {
+        let band = comfort_band(h, config.humidity_low, config.humidity_high);
+        format!("{:.*}% {}", precision.humidity, h, band.label())
+    });
+    let pressure_txt = format_reading(indoor.and_then(|d| d.pressure), |p| {
+        let pressure = units.format_pressure(p, precision.pressure);
+        match data.pressure_trend {
+            Some(trend) => format!("{} {}", pressure, trend.glyph()),
+            None => pressure,
+        }
+    });
+    let dewpoint_txt = format_reading(indoor.and_then(|d| d.dewpoint), |t| {
+        format!("Dew {}", units.format_temp(t, precision.temp))
+    });
+    let mut minor_text = format!("{}\n{}\n{}", humidity_txt, dewpoint_txt, pressure_txt);
+    if let Some(co2) = indoor.and_then(|d| d.co2) {
+        minor_text += &format!("\nCO2 {:.0}ppm", co2);
+    }
+    if let Some(voc) = indoor.and_then(|d| d.voc) {
+        minor_text += &format!("\nVOC {:.0}", voc);
+    }
+    draw_centered(display, &minor_text, left_bottom, minor_font)?;
+
+    // header naming the location over the outdoor columns
+    let header = layout.header;
+    let header_text_style = TextBoxStyleBuilder::new(Font6x8)
+        .text_color(Black)
+        .alignment(CenterAligned)
+        .vertical_alignment(CenterAligned)
+        .build();
+    TextBox::new(&header_label(config, data, now), header)
+        .into_styled(header_text_style)
+        .draw(display)?;
+    // middle outdoor temp
+    let middle_top = layout.outdoor_major;
+    let middle_bottom = layout.outdoor_minor;
+
+    let outdoor = data.outdoor.as_ref();
+    let temp_txt = format_reading(outdoor.and_then(|d| d.temp), |t| {
+        match config.outdoor_temp_source {
+            OutdoorTempSource::FeelsLike => format!("~{}", units.format_temp(t, precision.temp)),
+            OutdoorTempSource::Temp => units.format_temp(t, precision.temp),
+        }
+    });
+
+    // how much warmer it is inside, along the bottom with the temp moved up
+    let mut temp_box = middle_top;
+    if config.show_temp_delta {
+        let indoor_temp = indoor.and_then(|d| d.temp);
+        let delta = temp_delta(indoor_temp, outdoor.and_then(|d| d.temp));
+        let delta_txt = format!(
+            "inside {}",
+            format_reading(delta, |d| units.format_temp_delta(d, precision.temp))
+        );
+        let delta_style = TextBoxStyleBuilder::new(Font6x8)
+            .text_color(Black)
+            .alignment(CenterAligned)
+            .vertical_alignment(BottomAligned)
+            .build();
+        let delta_box = Rectangle::new(
+            middle_top.top_left,
+            middle_top.bottom_right - Point::new(0, 2),
+        );
+        TextBox::new(&delta_txt, delta_box)
+            .into_styled(delta_style)
+            .draw(display)?;
+        temp_box.bottom_right.y -= Font6x8::CHARACTER_SIZE.height as i32 + 2;
+    }
+    draw_centered(display, &temp_txt, temp_box, major_font)?;
+
+    let humidity_txt = format_reading(outdoor.and_then(|d| d.humidity), |h| {
+        format!("{:.*}%", precision.humidity, h)
+    });
+    let pressure_txt = format_reading(outdoor.and_then(|d| d.pressure), |p| {
+        units.format_pressure(p, precision.pressure)
+    });
+    let mut minor_text = format!("{}\n{}", humidity_txt, pressure_txt);
+    if let Some(aqi) = outdoor.and_then(|d| d.aqi) {
+        minor_text += &format!("\nAQI {}", aqi_label(aqi));
+    }
+    if let Some(uvi) = outdoor.and_then(|d| d.uvi).filter(|_| config.show_uvi) {
+        minor_text += &format!("\n{}", uvi_text(uvi));
+    }
+
+    // the sun line along the bottom gets the readings moved up out of its way
+    let mut minor_box = middle_bottom;
+    if let Some(sun_txt) = outdoor.and_then(|d| sun_text(d, now)) {
+        let sun_style = TextBoxStyleBuilder::new(Font6x8)
+            .text_color(Black)
+            .alignment(CenterAligned)
+            .vertical_alignment(BottomAligned)
+            .build();
+        let sun_box = Rectangle::new(
+            middle_bottom.top_left,
+            middle_bottom.bottom_right - Point::new(0, 3),
+        );
+        TextBox::new(&sun_txt, sun_box)
+            .into_styled(sun_style)
+            .draw(display)?;
+        minor_box.bottom_right.y -= Font6x8::CHARACTER_SIZE.height as i32 + 3;
+    }
+    draw_centered(display, &minor_text, minor_box, minor_font)?;
+
+    // when this was drawn, to spot a panel that stopped updating
+    let footer = layout.footer;
+    let footer_text_style = TextBoxStyleBuilder::new(Font6x8)
+        .text_color(Black)
+        .alignment(CenterAligned)
+        .vertical_alignment(CenterAligned)
+        .build();
+    TextBox::new(&updated_text(config, now), footer)
+        .into_styled(footer_text_style)
+        .draw(display)?;
+    if let Some(forecast) = layout.forecast {
+        draw_forecast(display, config, data, forecast, layout.forecast_days)?;
+    }
+    #[cfg(feature = "qr")]
+    if let (Some(bounds), Some(url)) = (layout.qr, &config.dashboard_url) {
+        draw_qr(display, url, bounds)?;
+    }
+
+    // last, so nothing drawn near the edges covers it. The whole canvas, as
+    // the section outlines are offset by the rotation and don't meet the edge.
+    if config.outer_border {
+        let (width, height) = canvas_size(display);
+        let line_style = PrimitiveStyleBuilder::new()
+            .stroke_color(Black)
+            .stroke_width(1)
+            .build();
+        Rectangle::new(Point::zero(), Point::new(width - 1, height - 1))
+            .into_styled(line_style)
+            .draw(display)?;
+    }
+
+    Ok(())
+}
+
+// inverted across the bottom of the canvas
+fn draw_rain_banner(display: &mut VarDisplay) -> Result<()> {
+    let (width, height) = canvas_size(display);
+    let banner = Rectangle::new(
+        Point::new(0, height - BANNER_HEIGHT),
+        Point::new(width, height),
+    );
+    banner
+        .into_styled(PrimitiveStyleBuilder::new().fill_color(Black).build())
+        .draw(display)?;
+    let banner_text_style = TextBoxStyleBuilder::new(Font6x8)
+        .text_color(White)
+        .alignment(CenterAligned)
+        .vertical_alignment(CenterAligned)
+        .build();
+    TextBox::new("BRING AN UMBRELLA", banner)
+        .into_styled(banner_text_style)
+        .draw(display)?;
+    Ok(())
+}
+
+// an inverted ! in the corner for when the newest indoor sample is too old
+fn draw_stale_marker(display: &mut VarDisplay) -> Result<()> {
+    let marker = Rectangle::new(Point::new(2, 2), Point::new(11, 18));
+    marker
+        .into_styled(PrimitiveStyleBuilder::new().fill_color(Black).build())
+        .draw(display)?;
+    let marker_text_style = TextBoxStyleBuilder::new(Font8x16)
+        .text_color(White)
+        .alignment(CenterAligned)
+        .vertical_alignment(CenterAligned)
+        .build();
+    TextBox::new("!", marker)
+        .into_styled(marker_text_style)
+        .draw(display)?;
+    Ok(())
+}
+
+// The warnings from draw, drawn again on their own in the same places for
+// the panel's colour layer, where black comes out red. They're still in the
+// black frame for a panel that's wired up as black and white.
+fn draw_accents(
+    display: &mut VarDisplay,
+    config: &Config,
+    data: &Data,
+    now: DateTime<Local>,
+) -> Result<()> {
+    if rain_expected(data, config) {
+        draw_rain_banner(display)?;
+    }
+    if indoor_is_stale(data, config, now.with_timezone(&Utc)) {
+        draw_stale_marker(display)?;
+    }
+    Ok(())
+}
+
+// the forecast, a strip of one box per day down right, for the first
+// days_shown days
+fn draw_forecast(
+    display: &mut VarDisplay,
+    config: &Config,
+    data: &Data,
+    right: Rectangle,
+    days_shown: usize,
+) -> Result<()> {
+    let units = config.units;
+    let precision = &config.precision;
+    let top = right.top_left.y;
+    let day_height = right.bottom_right.y - top;
+
+    let days = match &data.forecast {
+        Some(forecast_data) if !forecast_data.days.is_empty() => &forecast_data.days,
+        _ => {
+            draw_centered(display, PLACEHOLDER, right, config.minor_font)?;
+            return Ok(());
+        }
+    };
+
+    for (i, day) in days.iter().take(days_shown).enumerate() {
+        let day_box = Rectangle::new(
+            Point::new(
+                right.top_left.x,
+                top + day_height * i as i32 / days_shown as i32,
+            ),
+            Point::new(
+                right.bottom_right.x,
+                top + day_height * (i as i32 + 1) / days_shown as i32,
+            ),
+        );
+        let weekday = day
+            .date
+            .map_or(PLACEHOLDER.to_string(), |d| d.format("%a").to_string());
+
+        // the icon stands in for the condition when there's a code for it,
+        // with the text moved over to make room
+        let mut text_box = day_box;
+        let heading = match day.condition_id {
+            Some(id) => {
+                let size = icons::ICON_SIZE as i32;
+                let icon = ImageRaw::<BinaryColor>::new(
+                    icons::icon_for_condition(id),
+                    icons::ICON_SIZE,
+                    icons::ICON_SIZE,
+                );
+                let center_y = (day_box.top_left.y + day_box.bottom_right.y) / 2;
+                let position = Point::new(day_box.top_left.x + 4, center_y - size / 2);
+                Image::new(&icon, position).draw(display)?;
+                text_box.top_left.x += size + 6;
+                weekday
+            }
+            None => format!("{} {}", weekday, day.condition),
+        };
+        let day_text = format!(
+            "{}\n{}/{}\nPop {}\n{} {}",
+            heading,
+            units.format_temp(day.high, precision.temp),
+            units.format_temp(day.low, precision.temp),
+            format_pop(day.pop),
+            units.format_wind(day.wind_speed),
+            compass_point(day.wind_deg),
+        );
+        draw_aligned(display, &day_text, text_box, config.forecast_alignment)?;
+        if i > 0 {
+            draw_divider(
+                display,
+                config,
+                day_box.top_left,
+                Point::new(day_box.bottom_right.x, day_box.top_left.y),
+            )?;
+        }
+    }
+    Ok(())
+}
+
+// Where each section of draw goes, from one of the layout functions
+struct Layout {
+    indoor_major: Rectangle,
+    indoor_minor: Rectangle,
+    header: Rectangle,
+    outdoor_major: Rectangle,
+    outdoor_minor: Rectangle,
+    footer: Rectangle,
+    // left off when there's no room for it
+    forecast: Option<Rectangle>,
+    forecast_days: usize,
+    // for the dashboard_url QR code, when it's set
+    #[cfg(feature = "qr")]
+    qr: Option<Rectangle>,
+    dividers: Vec<(Point, Point)>,
+    // for the big readings, the layout can make more of major_font
+    major_font: FontChoice,
+}
+
+// Indoor, outdoor and the forecast a third of the width each. The header
+// only spans the outdoor columns and the footer only the forecast.
+fn three_column_layout(width: i32, height: i32, config: &Config) -> Layout {
+    let third = width / 3;
+    #[allow(unused_mut)]
+    let mut layout = Layout {
+        indoor_major: Rectangle::new(Point::new(0, 0), Point::new(third, height / 2)),
+        indoor_minor: Rectangle::new(Point::new(0, height / 2), Point::new(third, height)),
+        header: Rectangle::new(Point::new(third, 0), Point::new(width, HEADER_HEIGHT)),
+        outdoor_major: Rectangle::new(
+            Point::new(third, HEADER_HEIGHT),
+            Point::new(third * 2, height / 2),
+        ),
+        outdoor_minor: Rectangle::new(Point::new(third, height / 2), Point::new(third * 2, height)),
+        footer: Rectangle::new(
+            Point::new(third * 2, height - FOOTER_HEIGHT),
+            Point::new(width, height),
+        ),
+        forecast: Some(Rectangle::new(
+            Point::new(third * 2, HEADER_HEIGHT),
+            Point::new(width, height - FOOTER_HEIGHT),
+        )),
+        forecast_days: FORECAST_DAYS,
+        #[cfg(feature = "qr")]
+        qr: None,
+        dividers: vec![
+            (Point::new(third, 0), Point::new(third, height)),
+            (
+                Point::new(third * 2, HEADER_HEIGHT),
+                Point::new(third * 2, height),
+            ),
+            (Point::new(0, height / 2), Point::new(third * 2, height / 2)),
+            (
+                Point::new(third, HEADER_HEIGHT),
+                Point::new(width, HEADER_HEIGHT),
+            ),
+            (
+                Point::new(third * 2, height - FOOTER_HEIGHT),
+                Point::new(width, height - FOOTER_HEIGHT),
+            ),
+        ],
+        major_font: config.major_font,
+    };
+
+    // the QR code takes the last day's box
+    #[cfg(feature = "qr")]
+    if config.dashboard_url.is_some() {
+        let day_height = (height - HEADER_HEIGHT - FOOTER_HEIGHT) / FORECAST_DAYS as i32;
+        let qr_top = height - FOOTER_HEIGHT - day_height;
+        layout.forecast = Some(Rectangle::new(
+            Point::new(third * 2, HEADER_HEIGHT),
+            Point::new(width, qr_top),
+        ));
+        layout.forecast_days -= 1;
+        layout.qr = Some(Rectangle::new(
+            Point::new(third * 2, qr_top),
+            Point::new(width, height - FOOTER_HEIGHT),
+        ));
+        layout
+            .dividers
+            .push((Point::new(third * 2, qr_top), Point::new(width, qr_top)));
+    }
+    layout
+}
+
+// Just indoor and outdoor, half the width each, with the big readings a size
+// up to fill the room the forecast leaves. draw_centered still drops back
+// down for a reading too long for it.
+fn two_column_layout(width: i32, height: i32, config: &Config) -> Layout {
+    let half = width / 2;
+    #[allow(unused_mut)]
+    let mut layout = Layout {
+        indoor_major: Rectangle::new(Point::new(0, 0), Point::new(half, height / 2)),
+        indoor_minor: Rectangle::new(Point::new(0, height / 2), Point::new(half, height)),
+        header: Rectangle::new(Point::new(half, 0), Point::new(width, HEADER_HEIGHT)),
+        outdoor_major: Rectangle::new(
+            Point::new(half, HEADER_HEIGHT),
+            Point::new(width, height / 2),
+        ),
+        outdoor_minor: Rectangle::new(
+            Point::new(half, height / 2),
+            Point::new(width, height - FOOTER_HEIGHT),
+        ),
+        footer: Rectangle::new(
+            Point::new(half, height - FOOTER_HEIGHT),
+            Point::new(width, height),
+        ),
+        forecast: None,
+        forecast_days: 0,
+        #[cfg(feature = "qr")]
+        qr: None,
+        dividers: vec![
+            (Point::new(half, 0), Point::new(half, height)),
+            (Point::new(0, height / 2), Point::new(width, height / 2)),
+            (
+                Point::new(half, HEADER_HEIGHT),
+                Point::new(width, HEADER_HEIGHT),
+            ),
+            (
+                Point::new(half, height - FOOTER_HEIGHT),
+                Point::new(width, height - FOOTER_HEIGHT),
+            ),
+        ],
+        major_font: config.major_font.larger(),
+    };
+
+    // the QR code takes a square off the right of the outdoor readings
+    #[cfg(feature = "qr")]
+    if config.dashboard_url.is_some() {
+        let outdoor_minor = layout.outdoor_minor;
+        let left = width - (outdoor_minor.bottom_right.y - outdoor_minor.top_left.y);
+        layout.qr = Some(Rectangle::new(
+            Point::new(left, outdoor_minor.top_left.y),
+            outdoor_minor.bottom_right,
+        ));
+        layout.outdoor_minor.bottom_right.x = left;
+    }
+    layout
+}
+
+// The code as big as fits in bounds and centred in it, with a quiet zone of
+// QR_QUIET_ZONE modules cleared around it
+#[cfg(feature = "qr")]
+fn draw_qr(display: &mut VarDisplay, text: &str, bounds: Rectangle) -> Result<()> {
+    let code = qr::QrCode::encode(text.as_bytes())
+        .ok_or_else(|| Oops::Config(format!("{:?} is too long for a QR code", text)))?;
+    let modules = code.size() as i32 + 2 * QR_QUIET_ZONE;
+    // the rectangle includes both edges of bounds, size doesn't
+    let room = bounds.size() + Size::new(1, 1);
+    let scale = room.width.min(room.height) as i32 / modules;
+    if scale == 0 {
+        warn!("no room for the {0}x{0} dashboard QR code", modules);
+        return Ok(());
+    }
+    let side = modules * scale;
+    let origin = bounds.top_left
+        + Point::new(
+            (room.width as i32 - side) / 2,
+            (room.height as i32 - side) / 2,
+        );
+    Rectangle::new(origin, origin + Point::new(side - 1, side - 1))
+        .into_styled(PrimitiveStyleBuilder::new().fill_color(White).build())
+        .draw(display)?;
+    let dark = PrimitiveStyleBuilder::new().fill_color(Black).build();
+    for y in 0..code.size() {
+        for x in (0..code.size()).filter(|&x| code.is_dark(x, y)) {
+            let module = origin
+                + Point::new(
+                    (x as i32 + QR_QUIET_ZONE) * scale,
+                    (y as i32 + QR_QUIET_ZONE) * scale,
+                );
+            Rectangle::new(module, module + Point::new(scale - 1, scale - 1))
+                .into_styled(dark)
+                .draw(display)?;
+        }
+    }
+    Ok(())
+}
+
+// a line between two sections, unless dividers are turned off
+fn draw_divider(display: &mut VarDisplay, config: &Config, start: Point, end: Point) -> Result<()> {
+    if !config.dividers {
+        return Ok(());
+    }
+    let line_style = PrimitiveStyleBuilder::new()
+        .stroke_color(Black)
+        .stroke_width(config.divider_width)
+        .build();
+    Line::new(start, end)
+        .into_styled(line_style)
+        .draw(display)?;
+    Ok(())
+}
+
+// ERROR over the message, without anything fetched or configured so it works
+// however far a run got
+fn draw_error(display: &mut VarDisplay, msg: &str) -> Result<()> {
+    let (width, height) = canvas_size(display);
+    let title = Rectangle::new(Point::new(0, 0), Point::new(width, height / 3));
+    let body = Rectangle::new(Point::new(4, height / 3), Point::new(width - 4, height - 4));
+    draw_centered(display, "ERROR", title, FontChoice::Font12x16)?;
+    draw_centered(display, msg, body, FontChoice::Font6x8)
+}
+
+// Just text, centred in the biggest font it fits the panel in once it's
+// wrapped
+fn draw_message(display: &mut VarDisplay, text: &str) -> Result<()> {
+    let (width, height) = canvas_size(display);
+    let bounds = Rectangle::new(Point::new(4, 4), Point::new(width - 4, height - 4));
+    // the text box includes both edges of bounds, size doesn't
+    let room = bounds.size() + Size::new(1, 1);
+    let font = FontChoice::ALL
+        .into_iter()
+        .rev()
+        .find(|font| {
+            let character = font.character_size();
+            let columns = (room.width / character.width) as usize;
+            columns > 0 && wrapped_lines(text, columns) as u32 * character.height <= room.height
+        })
+        .unwrap_or(FontChoice::Font6x8);
+    draw_in_font(display, text, bounds, font)
+}
+
+// How many lines text takes up wrapped at spaces to `columns` characters as a
+// TextBox does it, with words too long for a line broken across lines
+fn wrapped_lines(text: &str, columns: usize) -> usize {
+    text.lines()
+        .map(|line| {
+            let (mut lines, mut used) = (1, 0);
+            for word in line.split(' ') {
+                let len = word.chars().count();
+                if used > 0 && used + 1 + len > columns {
+                    lines += 1;
+                    used = 0;
+                }
+                if used > 0 {
+                    used += 1;
+                }
+                used += len;
+                while used > columns {
+                    lines += 1;
+                    used -= columns;
+                }
+            }
+            lines
+        })
+        .sum()
+}
+
+// A line through the values scaled to fill rect top to bottom, a flat series
+// is drawn through the middle. Nothing is drawn for fewer than two values.
+fn draw_sparkline(display: &mut VarDisplay, data: &[f64], rect: Rectangle) -> Result<()> {
+    if data.len() < 2 {
+        return Ok(());
+    }
+    let min = data.iter().cloned().fold(f64::INFINITY, f64::min);
+    let max = data.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    let (left, top) = (rect.top_left.x, rect.top_left.y);
+    let width = (rect.bottom_right.x - left) as f64;
+    let height = (rect.bottom_right.y - top) as f64;
+
+    let point = |i: usize, value: f64| {
+        let x = left + (i as f64 * width / (data.len() - 1) as f64).round() as i32;
+        let scaled = if max > min {
+            (value - min) / (max - min)
+        } else {
+            0.5
+        };
+        let y = top + ((1.0 - scaled) * height).round() as i32;
+        Point::new(x, y)
+    };
+
+    let line_style = PrimitiveStyleBuilder::new()
+        .stroke_color(Black)
+        .stroke_width(1)
+        .build();
+    for (i, pair) in data.windows(2).enumerate() {
+        Line::new(point(i, pair[0]), point(i + 1, pair[1]))
+            .into_styled(line_style)
+            .draw(display)?;
+    }
+    Ok(())
+}
+
+// the location, and the moon phase after it when that's turned on
+fn header_label(config: &Config, data: &Data, now: DateTime<Local>) -> String {
+    let location = config.location(now.weekday());
+    if location.at_null_island() {
+        return "check lat/lon, set to 0,0".to_string();
+    }
+    let phase = data
+        .forecast
+        .as_ref()
+        .and_then(|forecast| forecast.moon_phase)
+        .filter(|_| config.show_moon_phase);
+    match phase {
+        Some(phase) => format!("{} | {}", location.label(), moon_phase_name(phase)),
+        None => location.label(),
+    }
+}
+
+// OpenWeather's phase runs from 0 (new) through 0.5 (full) back to 1 (new),
+// each name covers the eighth of the cycle around it
+fn moon_phase_name(phase: f64) -> &'static str {
+    const NAMES: [&str; 8] = [
+        "New Moon",
+        "Waxing Crescent",
+        "First Quarter",
+        "Waxing Gibbous",
+        "Full Moon",
+        "Waning Gibbous",
+        "Last Quarter",
+        "Waning Crescent",
+    ];
+    NAMES[(phase.rem_euclid(1.0) * 8.0).round() as usize % 8]
+}
+
+// when this was drawn, in updated_format
+fn updated_text(config: &Config, now: DateTime<Local>) -> String {
+    format!("updated {}", now.format(&config.updated_format))
+}
+
+// The drawable area once the panel's rotation is applied
+fn canvas_size(display: &VarDisplay) -> (i32, i32) {
+    let size = display.size();
+    match display.rotation() {
+        DisplayRotation::Rotate0 | DisplayRotation::Rotate180 => {
+            (size.width as i32, size.height as i32)
+        }
+        DisplayRotation::Rotate90 | DisplayRotation::Rotate270 => {
+            (size.height as i32, size.width as i32)
+        }
+    }
+}
+
+// Draws small text in bounds, centered vertically and lined up across as asked.
+// The alignments are separate types to embedded-text, so each gets a style.
+fn draw_aligned(
+    display: &mut VarDisplay,
+    text: &str,
+    bounds: Rectangle,
+    alignment: TextAlignment,
+) -> Result<()> {
+    let text_box = TextBox::new(text, bounds);
+    let style = TextBoxStyleBuilder::new(Font6x8)
+        .text_color(Black)
+        .vertical_alignment(CenterAligned);
+    match alignment {
+        TextAlignment::Left => text_box
+            .into_styled(style.alignment(LeftAligned).build())
+            .draw(display)?,
+        TextAlignment::Center => text_box
+            .into_styled(style.alignment(CenterAligned).build())
+            .draw(display)?,
+        TextAlignment::Right => text_box
+            .into_styled(style.alignment(RightAligned).build())
+            .draw(display)?,
+    };
+    Ok(())
+}
+
+// Draws text centered both ways in bounds, with the font picked at runtime and
+// shrunk if the text wouldn't fit
+fn draw_centered(
+    display: &mut VarDisplay,
+    text: &str,
+    bounds: Rectangle,
+    font: FontChoice,
+) -> Result<()> {
+    // the text box includes both edges of bounds, size doesn't
+    let room = bounds.size() + Size::new(1, 1);
+    draw_in_font(display, text, bounds, font.fitting(text, room))
+}
+
+// draw_centered without dropping to a smaller font, the text box wraps
+// anything too wide for it
+fn draw_in_font(
+    display: &mut VarDisplay,
+    text: &str,
+    bounds: Rectangle,
+    font: FontChoice,
+) -> Result<()> {
+    fn draw_in<F: Font + Copy>(
+        display: &mut VarDisplay,
+        text: &str,
+        bounds: Rectangle,
+        font: F,
+    ) -> Result<()> {
+        let style = TextBoxStyleBuilder::new(font)
+            .text_color(Black)
+            .alignment(CenterAligned)
+            .vertical_alignment(CenterAligned)
+            .build();
+        TextBox::new(text, bounds)
+            .into_styled(style)
+            .draw(display)?;
+        Ok(())
+    }
+
+    match font {
+        FontChoice::Font6x8 => draw_in(display, text, bounds, Font6x8),
+        FontChoice::Font6x12 => draw_in(display, text, bounds, Font6x12),
+        FontChoice::Font8x16 => draw_in(display, text, bounds, Font8x16),
+        FontChoice::Font12x16 => draw_in(display, text, bounds, Font12x16),
+        FontChoice::Font24x32 => draw_in(display, text, bounds, Font24x32),
+    }
+}
+
+// True if anything on the panel would look different, readings only count as
+// changed once they've moved by more than their epsilon
+fn changed(last: &LastDrawn, current: &LastDrawn, epsilon: &ChangeEpsilon) -> bool {
+    let epsilons = [epsilon.temp, epsilon.humidity, epsilon.pressure];
+    let indoor_epsilons = [
+        epsilon.temp,
+        epsilon.humidity,
+        epsilon.pressure,
+        epsilon.temp,
+        epsilon.co2,
+        epsilon.voc,
+    ];
+    let indoor = |drawn: &LastDrawn| {
+        let indoor = drawn.data.newest_indoor()?;
+        Some([
+            indoor.temp,
+            indoor.humidity,
+            indoor.pressure,
+            indoor.dewpoint,
+            indoor.co2,
+            indoor.voc,
+        ])
+    };
+    let outdoor = |drawn: &LastDrawn| {
+        let outdoor = drawn.data.outdoor.as_ref()?;
+        Some([outdoor.temp, outdoor.humidity, outdoor.pressure])
+    };
+    let day = |day: &DayForecast| {
+        Some([
+            Some(day.high),
+            Some(day.low),
+            Some(day.pop),
+            Some(day.wind_speed),
+            Some(day.wind_deg),
+        ])
+    };
+    let day_epsilons = [
+        epsilon.temp,
+        epsilon.temp,
+        epsilon.pop,
+        epsilon.wind_speed,
+        epsilon.wind_deg,
+    ];
+    let forecast_changed = match (&last.data.forecast, &current.data.forecast) {
+        (Some(last), Some(current)) => {
+            last.days.len() != current.days.len()
+                || last.days.iter().zip(&current.days).any(|(last, current)| {
+                    last.date != current.date
+                        || last.condition != current.condition
+                        || last.condition_id != current.condition_id
+                        || moved(day(last), day(current), day_epsilons)
+                })
+        }
+        (None, None) => false,
+        _ => true,
+    };
+    // drawn as a whole percentage
+    let battery = |drawn: &LastDrawn| {
+        drawn
+            .data
+            .battery
+            .as_ref()
+            .map(|battery| battery.percent.clamp(0.0, 100.0).round() as i64)
+    };
+
+    // the newest point's mean shifts on every run, so like the readings a
+    // point only counts once it's moved by more than the temp epsilon
+    let (last_history, history) = (&last.data.indoor_history, &current.data.indoor_history);
+    let history_changed = last_history.len() != history.len()
+        || last_history
+            .iter()
+            .zip(history)
+            .any(|(last, current)| (last - current).abs() > epsilon.temp);
+
+    // only the name is drawn, so the phase creeping along doesn't count
+    let moon = |drawn: &LastDrawn| {
+        drawn
+            .data
+            .forecast
+            .as_ref()
+            .and_then(|forecast| forecast.moon_phase)
+            .map(moon_phase_name)
+    };
+
+    let room = |drawn: &LastDrawn| {
+        drawn
+            .data
+            .newest_indoor()
+            .map(|indoor| indoor.label.clone())
+    };
+
+    let aqi = |drawn: &LastDrawn| drawn.data.outdoor.as_ref().and_then(|d| d.aqi);
+    // drawn as a whole number, so only a change in that counts
+    let uvi = |drawn: &LastDrawn| {
+        drawn
+            .data
+            .outdoor
+            .as_ref()
+            .and_then(|d| d.uvi)
+            .map(|uvi| uvi.round() as i64)
+    };
+
+    room(last) != room(current)
+        || last.data.pressure_trend != current.data.pressure_trend
+        || aqi(last) != aqi(current)
+        || uvi(last) != uvi(current)
+        || moon(last) != moon(current)
+        || moved(indoor(last), indoor(current), indoor_epsilons)
+        || moved(outdoor(last), outdoor(current), epsilons)
+        || forecast_changed
+        || last.indoor_stale != current.indoor_stale
+        || last.rain_banner != current.rain_banner
+        || last.text != current.text
+        || battery(last) != battery(current)
+        || history_changed
+}
+
+// a missing source or reading counts as a change if it wasn't missing before
+fn moved<const N: usize>(
+    last: Option<[Option<f64>; N]>,
+    current: Option<[Option<f64>; N]>,
+    epsilons: [f64; N],
+) -> bool {
+    match (last, current) {
+        (Some(last), Some(current)) => {
+            last.iter()
+                .zip(current)
+                .zip(epsilons)
+                .any(|((last, current), epsilon)| match (last, current) {
+                    (Some(last), Some(current)) => (last - current).abs() > epsilon,
+                    (None, None) => false,
+                    _ => true,
+                })
+        }
+        (None, None) => false,
+        _ => true,
+    }
+}
+
+fn load_last_drawn(path: &Path) -> Option<LastDrawn> {
+    let json = fs::read_to_string(path).ok()?;
+    serde_json::from_str(&json).ok()
+}
+
+fn save_last_drawn(path: &Path, drawn: &LastDrawn) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let json = serde_json::to_string(drawn)?;
+    fs::write(path, json)?;
+    Ok(())
+}
+
+// The last good OpenWeather response, so a rate limited or failed fetch can
+// still draw something recent instead of placeholders
+#[derive(Serialize, Deserialize)]
+struct CachedWeather {
+    fetched_at: DateTime<Utc>,
+    // lat and lon it's for, older caches without one were for the only
+    // location there was
+    #[serde(default)]
+    location: Option<(f64, f64)>,
+    response: serde_json::Value,
+}
+
+// OpenWeather calls made on a UTC day, which is when the free tier's daily
+// budget resets
+#[derive(Serialize, Deserialize)]
+struct CallCount {
+    day: NaiveDate,
+    calls: u32,
+}
+
+// a missing or unreadable count, or one from another day, is no calls
+fn calls_on(path: &Path, day: NaiveDate) -> u32 {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|json| serde_json::from_str::<CallCount>(&json).ok())
+        .filter(|count| count.day == day)
+        .map_or(0, |count| count.calls)
+}
+
+fn under_rate_limit(path: &Path, cap: u32, today: NaiveDate) -> bool {
+    calls_on(path, today) < cap
+}
+
+fn save_call_count(path: &Path, day: NaiveDate, calls: u32) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, serde_json::to_string(&CallCount { day, calls })?)?;
+    Ok(())
+}
+
+// only a response for the location that's being shown
+fn load_cached_weather(
+    path: &Path,
+    location: &Location,
+    max_age: chrono::Duration,
+    now: DateTime<Utc>,
+) -> Option<serde_json::Value> {
+    let json = fs::read_to_string(path).ok()?;
+    let cached: CachedWeather = serde_json::from_str(&json).ok()?;
+    if is_stale(cached.fetched_at, now, max_age) {
+        return None;
+    }
+    if cached
+        .location
+        .is_some_and(|cached| cached != (location.lat, location.lon))
+    {
+        return None;
+    }
+    Some(cached.response)
+}
+
+fn save_cached_weather(
+    path: &Path,
+    location: &Location,
+    response: &serde_json::Value,
+    now: DateTime<Utc>,
+) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let cached = CachedWeather {
+        fetched_at: now,
+        location: Some((location.lat, location.lon)),
+        response: response.clone(),
+    };
+    fs::write(path, serde_json::to_string(&cached)?)?;
+    Ok(())
+}
+
+// current.dt is when the conditions were observed, which lags a little, so
+// only a difference well past that hints at a clock that isn't being synced
+const CLOCK_SKEW_WARNING_MINUTES: i64 = 15;
+
+// a wrong clock throws off quiet hours and the updated time, nothing else
+// changes for it
+fn warn_on_clock_skew(response: &serde_json::Value) {
+    if let Some(dt) = response["current"]["dt"].as_i64() {
+        let skew = clock_skew(Utc::now(), dt);
+        if skew.num_minutes().abs() > CLOCK_SKEW_WARNING_MINUTES {
+            warn!(
+                "system clock is {} minutes off from OpenWeather's, is it synced?",
+                skew.num_minutes()
+            );
+        }
+    }
+}
+
+// how far the system clock is ahead of a unix timestamp from a server,
+// negative when it's behind
+fn clock_skew(system: DateTime<Utc>, server_dt: i64) -> chrono::Duration {
+    chrono::Duration::seconds(system.timestamp() - server_dt)
+}
+
+// Writes whatever readings we have as gauges in the Prometheus text format,
+// going through a temporary file so node_exporter never sees half of one
+fn write_prometheus(path: &Path, data: &Data, units: Units) -> Result<()> {
+    let (temp, pressure) = units.prometheus_units();
+    let mut samples: Vec<(String, String, Option<f64>)> = Vec::new();
+    for indoor in &data.indoor {
+        let labels = format!("{{room=\"{}\"}}", prometheus_label(&indoor.label));
+        for (name, value) in [
+            (format!("malter_indoor_temp_{}", temp), indoor.temp),
+            (
+                "malter_indoor_humidity_percent".to_string(),
+                indoor.humidity,
+            ),
+            (
+                format!("malter_indoor_pressure_{}", pressure),
+                indoor.pressure,
+            ),
+            (format!("malter_indoor_dewpoint_{}", temp), indoor.dewpoint),
+            ("malter_indoor_co2_ppm".to_string(), indoor.co2),
+            ("malter_indoor_voc".to_string(), indoor.voc),
+        ] {
+            samples.push((name, labels.clone(), value));
+        }
+    }
+    if let Some(outdoor) = &data.outdoor {
+        samples.push((
+            format!("malter_outdoor_temp_{}", temp),
+            String::new(),
+            outdoor.temp,
+        ));
+        samples.push((
+            "malter_outdoor_humidity_percent".to_string(),
+            String::new(),
+            outdoor.humidity,
+        ));
+        samples.push((
+            format!("malter_outdoor_pressure_{}", pressure),
+            String::new(),
+            outdoor.pressure,
+        ));
+        samples.push((
+            "malter_outdoor_aqi".to_string(),
+            String::new(),
+            outdoor.aqi.map(f64::from),
+        ));
+        samples.push(("malter_outdoor_uvi".to_string(), String::new(), outdoor.uvi));
+    }
+    if let Some(today) = data.forecast.as_ref().and_then(|f| f.days.first()) {
+        samples.push((
+            format!("malter_forecast_high_{}", temp),
+            String::new(),
+            Some(today.high),
+        ));
+        samples.push((
+            format!("malter_forecast_low_{}", temp),
+            String::new(),
+            Some(today.low),
+        ));
+        samples.push((
+            "malter_forecast_pop_ratio".to_string(),
+            String::new(),
+            Some(today.pop),
+        ));
+    }
+
+    let samples: Vec<(String, String, f64)> = samples
+        .into_iter()
+        .filter_map(|(name, labels, value)| Some((name, labels, value?)))
+        .collect();
+
+    // every sample of a metric has to follow its TYPE line
+    let mut text = String::new();
+    let mut written: Vec<&str> = Vec::new();
+    for (name, _, _) in &samples {
+        if written.contains(&name.as_str()) {
+            continue;
+        }
+        written.push(name);
+        text.push_str(&format!("# TYPE {} gauge\n", name));
+        for (_, labels, value) in samples.iter().filter(|(n, _, _)| n == name) {
+            text.push_str(&format!("{}{} {}\n", name, labels, value));
+        }
+    }
+
+    let tmp = path.with_extension("prom.tmp");
+    fs::write(&tmp, text)?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+// label values escape backslash, double quote and newline
+fn prometheus_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+// Where state that needs to survive between runs is kept
+fn cache_dir() -> PathBuf {
+    let base = env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .unwrap_or_else(env::temp_dir);
+    base.join("malter")
+}
+
+// Pushes the frame to the panel, using a quick (partial) refresh when enabled
+// and doing a full refresh every `full_refresh_every` updates to clear ghosting.
+//
+// A quick refresh works by flipping between the controller's two frame
+// memories, which are lost when it goes to sleep, so the previous frame is
+// kept on disk and written back first.
+fn refresh<SPI, CS, BUSY, DC, RST>(
+    epd: &mut AwakeEpd<SPI, CS, BUSY, DC, RST>,
+    buffer: &[u8],
+    accents: Option<&[u8]>,
+    config: &Config,
+) -> Result<()>
+where
+    SPI: Write<u8>,
+    SPI::Error: fmt::Display,
+    CS: OutputPin,
+    BUSY: InputPin,
+    DC: OutputPin,
+    RST: OutputPin,
+{
+    let path = cache_dir().join("frame.bin");
+    let previous = load_frame(&path);
+
+    let since_full = match previous {
+        Some((since_full, previous))
+            if config.partial_refresh
+                && config.panel.supports_quick_refresh()
+                && since_full + 1 < config.full_refresh_every
+                && previous.len() == buffer.len() =>
+        {
+            epd.set_lut(RefreshLUT::QUICK)?;
+            epd.update_and_display_frame(&previous)?;
+            epd.update_and_display_frame(buffer)?;
+            since_full + 1
+        }
+        _ => {
+            epd.set_lut(RefreshLUT::FULL)?;
+            match accents {
+                Some(accents) => epd.update_and_display_color_frame(buffer, accents)?,
+                None => epd.update_and_display_frame(buffer)?,
+            }
+            0
+        }
+    };
+
+    save_frame(&path, since_full, buffer)
+}
+
+// frame.bin is the number of quick refreshes since the last full one as a
+// little endian u32, then the raw frame buffer
+fn load_frame(path: &Path) -> Option<(u32, Vec<u8>)> {
+    let bytes = fs::read(path).ok()?;
+    if bytes.len() < 4 {
+        return None;
+    }
+    let (count, frame) = bytes.split_at(4);
+    let count = u32::from_le_bytes(count.try_into().ok()?);
+    Some((count, frame.to_vec()))
+}
+
+fn save_frame(path: &Path, since_full: u32, buffer: &[u8]) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut bytes = since_full.to_le_bytes().to_vec();
+    bytes.extend_from_slice(buffer);
+    fs::write(path, bytes)?;
+    Ok(())
+}
+
+// spi_mode was checked to be 0-3 by Config::validate
+fn spi_mode_flags(mode: u8) -> spidev::SpiModeFlags {
+    match mode {
+        1 => spidev::SpiModeFlags::SPI_MODE_1,
+        2 => spidev::SpiModeFlags::SPI_MODE_2,
+        3 => spidev::SpiModeFlags::SPI_MODE_3,
+        _ => spidev::SpiModeFlags::SPI_MODE_0,
+    }
+}
+
+// How long a pin's sysfs files get to show up after it's exported
+const PIN_EXPORT_TIMEOUT: Duration = Duration::from_secs(2);
+
+// try_get_epd, retried epd_init_retries times on a failure with a pause that
+// doubles each time, as send_with_retry does for requests
+fn get_epd(config: &Config) -> Result<AwakeEpd> {
+    let mut delay = Duration::from_millis(500);
+    let mut attempt = 0;
+    loop {
+        match try_get_epd(config) {
+            Err(e) if attempt < config.epd_init_retries => {
+                attempt += 1;
+                warn!(
+                    "setting up the EPD failed: {}, retrying in {:?} ({}/{})",
+                    e, delay, attempt, config.epd_init_retries
+                );
+                thread::sleep(delay);
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+}
+
+// exports a gpio pin and waits for it to be ready, the error naming the pin
+// and the step that failed
+fn export_pin(name: &str, number: u64, direction: Direction) -> Result<Pin> {
+    let failed = |step: &str, e: sysfs_gpio::Error| {
+        Oops::Display(format!("{} pin (gpio {}) {}: {}", name, number, step, e))
+    };
+    let pin = Pin::new(number);
+    pin.export().map_err(|e| failed("export failed", e))?;
+    let start = Instant::now();
+    while !pin.is_exported() {
+        if start.elapsed() > PIN_EXPORT_TIMEOUT {
+            return Err(Oops::Display(format!(
+                "{} pin (gpio {}) still isn't exported after {:?}",
+                name, number, PIN_EXPORT_TIMEOUT
+            )));
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    pin.set_direction(direction)
+        .map_err(|e| failed("setting the direction failed", e))?;
+    if direction == Direction::Out {
+        pin.set_value(1)
+            .map_err(|e| failed("setting it high failed", e))?;
+    }
+    Ok(pin)
+}
+
+fn try_get_epd(config: &Config) -> Result<AwakeEpd> {
+    let pins = &config.pins;
+    let spi_failed = |step: &str, e: std::io::Error| {
+        Oops::Display(format!("{} /dev/spidev0.0 failed: {}", step, e))
+    };
+    // Configure SPI
+    let mut spi = Spidev::open("/dev/spidev0.0").map_err(|e| spi_failed("opening", e))?;
+    let options = SpidevOptions::new()
+        .bits_per_word(8)
+        .max_speed_hz(config.spi_speed_hz)
+        .mode(spi_mode_flags(config.spi_mode))
+        .build();
+    spi.configure(&options)
+        .map_err(|e| spi_failed("configuring", e))?;
+
+    // Configure Digital I/O Pin to be used as Chip Select for SPI
+    let cs = export_pin("cs", pins.cs, Direction::Out)?;
+    let busy = export_pin("busy", pins.busy, Direction::In)?;
+    let busy_timeout = Duration::from_secs(config.busy_timeout_secs);
+    let busy_timed_out = Rc::new(Cell::new(false));
+    let busy = BusyPin {
+        pin: busy,
+        timeout: busy_timeout,
+        busy_since: Cell::new(None),
+        timed_out: busy_timed_out.clone(),
+    };
+    let dc = export_pin("dc", pins.dc, Direction::Out)?;
+    let rst = export_pin("rst", pins.rst, Direction::Out)?;
+
+    let epd = Epd::new(config.panel, &mut spi, cs, busy, dc, rst, &mut Delay {})?;
+    AwakeEpd::new(epd, spi, busy_timed_out, busy_timeout)
+}
+
+// Writes a 1-bit frame buffer out as a greyscale png, turned the same way as
+// the physical display. With accents it's a palette of black, white and red,
+// red wherever accents is black.
+fn write_png(
+    path: &str,
+    buffer: &[u8],
+    accents: Option<&[u8]>,
+    width: u32,
+    height: u32,
+    rotation: DisplayRotation,
+) -> Result<()> {
+    let (png_width, png_height) = rotated_size(width, height, rotation);
+    let bits: usize = if accents.is_some() { 2 } else { 1 };
+
+    // each scanline is a filter type byte (0, none) followed by the packed pixels
+    let row_bytes = (png_width as usize * bits).div_ceil(8);
+    let mut raw = Vec::with_capacity((row_bytes + 1) * png_height as usize);
+    for y in 0..png_height {
+        raw.push(0);
+        let mut packed = vec![0u8; row_bytes];
+        for x in 0..png_width {
+            // black 0, white 1 and red 2, the same as greyscale for 1 bit
+            let value = match accents {
+                Some(accents) if !is_white(accents, width, height, rotation, x, y) => 2,
+                _ => is_white(buffer, width, height, rotation, x, y) as u8,
+            };
+            let bit = x as usize * bits;
+            packed[bit / 8] |= value << (8 - bits - bit % 8);
+        }
+        raw.extend_from_slice(&packed);
+    }
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&png_width.to_be_bytes());
+    header.extend_from_slice(&png_height.to_be_bytes());
+    // greyscale or a palette, default compression/filter, no interlace
+    let color_type = if accents.is_some() { 3 } else { 0 };
+    header.extend_from_slice(&[bits as u8, color_type, 0, 0, 0]);
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    png_chunk(&mut png, b"IHDR", &header);
+    if accents.is_some() {
+        png_chunk(&mut png, b"PLTE", &[0, 0, 0, 255, 255, 255, 255, 0, 0]);
+    }
+    png_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+    png_chunk(&mut png, b"IEND", &[]);
+
+    fs::write(path, png)?;
+    Ok(())
+}
+
+/// A frame buffer as text, turned like the physical display, for a look at it
+/// in a terminal. Each character is a 2x2 block of pixels drawn with the
+/// Unicode quadrant characters, black pixels filled in, so a 296x128 frame is
+/// 148 columns by 64 lines.
+pub fn buffer_to_ascii(
+    buffer: &[u8],
+    width: u32,
+    height: u32,
+    rotation: DisplayRotation,
+) -> String {
+    // indexed by the black quadrants, top left 1, top right 2, bottom left 4
+    // and bottom right 8
+    const QUADRANTS: [char; 16] = [
+        ' ', '▘', '▝', '▀', '▖', '▌', '▞', '▛', '▗', '▚', '▐', '▜', '▄', '▙', '▟', '█',
+    ];
+    let (text_width, text_height) = rotated_size(width, height, rotation);
+    let black = |x: u32, y: u32| {
+        x < text_width && y < text_height && !is_white(buffer, width, height, rotation, x, y)
+    };
+    let mut text = String::new();
+    for y in (0..text_height).step_by(2) {
+        for x in (0..text_width).step_by(2) {
+            let quadrants = [(0, 0), (1, 0), (0, 1), (1, 1)];
+            let index = quadrants
+                .iter()
+                .enumerate()
+                .filter(|(_, (dx, dy))| black(x + dx, y + dy))
+                .fold(0, |index, (bit, _)| index | 1 << bit);
+            text.push(QUADRANTS[index]);
+        }
+        text.push('\n');
+    }
+    text
+}
+
+// the frame's size once it's turned by rotation
+fn rotated_size(width: u32, height: u32, rotation: DisplayRotation) -> (u32, u32) {
+    match rotation {
+        DisplayRotation::Rotate0 | DisplayRotation::Rotate180 => (width, height),
+        DisplayRotation::Rotate90 | DisplayRotation::Rotate270 => (height, width),
+    }
+}
+
+// Whether the pixel at x, y of the turned frame is white. The buffer is in
+// the panel's native orientation with 8 pixels per byte, high bit first, and
+// a set bit meaning white.
+fn is_white(
+    buffer: &[u8],
+    width: u32,
+    height: u32,
+    rotation: DisplayRotation,
+    x: u32,
+    y: u32,
+) -> bool {
+    let (col, row) = match rotation {
+        DisplayRotation::Rotate0 => (x, y),
+        DisplayRotation::Rotate90 => (width - 1 - y, x),
+        DisplayRotation::Rotate180 => (width - 1 - x, height - 1 - y),
+        DisplayRotation::Rotate270 => (y, height - 1 - x),
+    };
+    let byte = buffer[(row * (width / 8) + col / 8) as usize];
+    byte & (0x80 >> (col % 8)) != 0
+}
+
+fn png_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&[&kind[..], data].concat());
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+// A zlib stream of uncompressed deflate blocks, the frames are tiny so
+// there's no point pulling in a real compressor
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(u16::MAX as usize).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        out.push(if blocks.peek().is_none() { 1 } else { 0 });
+        let len = block.len() as u16;
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    out.extend_from_slice(&((b << 16) | a).to_be_bytes());
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(y: i32, m: u32, d: u32, h: u32, min: u32) -> DateTime<Utc> {
+        Utc.ymd(y, m, d).and_hms(h, min, 0)
+    }
+
+    // a config that validates, with `extra` keys added on top
+    fn test_config(extra: &str) -> Config {
+        let config: Config = toml::from_str(&format!(
+            "lat = 45.42\nlon = -75.69\nopenweather_api_key = \"key\"\n{}",
+            extra
+        ))
+        .unwrap();
+        config.validate().unwrap();
+        config
+    }
+
+    // a room, outdoor readings and three days of forecast from `now`
+    fn test_data(now: DateTime<Local>) -> Data {
+        let today = now.date().naive_local();
+        let day = |days: i64, condition: &str, condition_id: u32| DayForecast {
+            date: Some(today + chrono::Duration::days(days)),
+            high: 12.0,
+            low: -3.5,
+            condition: condition.to_string(),
+            condition_id: Some(condition_id),
+            pop: 0.4,
+            wind_speed: 4.2,
+            wind_deg: 270.0,
+        };
+        Data {
+            indoor: vec![IndoorData {
+                label: "indoor".to_string(),
+                sampled_at: Some(now.with_timezone(&Utc) - chrono::Duration::minutes(5)),
+                temp: Some(21.5),
+                humidity: Some(40.0),
+                pressure: Some(1013.0),
+                dewpoint: Some(7.5),
+                co2: None,
+                voc: None,
+            }],
+            outdoor: Some(OutdoorData {
+                temp: Some(-12.3),
+                humidity: Some(80.0),
+                pressure: Some(1001.0),
+                sunrise: None,
+                sunset: None,
+                aqi: None,
+                uvi: Some(3.0),
+            }),
+            forecast: Some(ForecastData {
+                days: vec![
+                    day(0, "Snow", 601),
+                    day(1, "Clouds", 803),
+                    day(2, "Clear", 800),
+                ],
+                moon_phase: Some(0.3),
+            }),
+            battery: None,
+            indoor_history: (0..48).map(|i| 20.0 + (i as f64 / 5.0).sin()).collect(),
+            pressure_trend: Some(Trend::Steady),
+        }
+    }
+
+    // somewhere under the temp dir for one test to write to, emptied first
+    fn temp_dir(test: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("malter-{}-{}", std::process::id(), test));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn imperial_temps_are_fahrenheit() {
+        let units = Units::Imperial;
+        assert_eq!(units.format_temp(units.convert_temp(20.0), 1), "68.0F");
+        assert_eq!(
+            Units::Metric.format_temp(Units::Metric.convert_temp(20.0), 1),
+            "20.0C"
+        );
+        assert_eq!(
+            units.format_pressure(units.convert_pressure(1013.25), None),
+            "29.92 inHg"
+        );
+    }
+
+    #[test]
+    fn influxql_rows_keep_missing_null_and_non_numeric_as_none() {
+        let body = r#"{"results":[{"statement_id":0,"series":[{"name":"indoor",
+            "columns":["time","temperature","pressure","humidity"],
+            "values":[["2026-10-14T12:00:00Z",21.5,null,"n/a"]]}]}]}"#;
+        let row = InfluxQl.parse_latest(body).unwrap().unwrap();
+        assert_eq!(row.sampled_at, Some(utc(2026, 10, 14, 12, 0)));
+        assert_eq!(row.temp, Some(21.5));
+        assert_eq!(row.pressure, None);
+        assert_eq!(row.humidity, None);
+        // not asked for so not a column at all
+        assert_eq!(row.co2, None);
+    }
+
+    #[test]
+    fn indoor_is_stale_only_past_stale_after_minutes() {
+        let threshold = chrono::Duration::minutes(30);
+        let now = utc(2026, 10, 14, 12, 0);
+        let sampled = |minutes_ago: i64, seconds_ago: i64| {
+            let sampled_at = now
+                - chrono::Duration::minutes(minutes_ago)
+                - chrono::Duration::seconds(seconds_ago);
+            is_stale(sampled_at, now, threshold)
+        };
+        assert!(!sampled(29, 59));
+        assert!(!sampled(30, 0));
+        assert!(sampled(30, 1));
+    }
+
+    #[test]
+    fn indoor_without_a_sample_time_isnt_stale() {
+        let config = test_config("");
+        let now = utc(2026, 10, 14, 12, 0);
+        let mut data = test_data(now.with_timezone(&Local));
+        data.indoor[0].sampled_at = None;
+        assert!(!indoor_is_stale(&data, &config, now));
+        data.indoor.clear();
+        assert!(!indoor_is_stale(&data, &config, now));
+    }
+
+    #[test]
+    fn compass_points_and_their_edges() {
+        assert_eq!(compass_point(0.0), "N");
+        assert_eq!(compass_point(22.4), "N");
+        // halfway goes to the next point round
+        assert_eq!(compass_point(22.5), "NE");
+        assert_eq!(compass_point(90.0), "E");
+        assert_eq!(compass_point(202.5), "SW");
+        assert_eq!(compass_point(337.4), "NW");
+        assert_eq!(compass_point(337.5), "N");
+        assert_eq!(compass_point(360.0), "N");
+    }
+
+    #[test]
+    fn compass_points_wrap_around() {
+        assert_eq!(compass_point(405.0), "NE");
+        assert_eq!(compass_point(720.0), "N");
+        assert_eq!(compass_point(-45.0), "NW");
+        assert_eq!(compass_point(-10.0), "N");
+    }
+
+    #[test]
+    fn unchanged_readings_are_not_a_change() {
+        let config = test_config("");
+        let now = Local.ymd(2026, 10, 14).and_hms(12, 0, 0);
+        let last = LastDrawn::new(&config, test_data(now), now);
+        let current = LastDrawn::new(&config, test_data(now), now);
+        assert!(!changed(&last, &current, &config.change_epsilon));
+    }
+
+    #[test]
+    fn readings_change_past_their_epsilon() {
+        let config = test_config("[change_epsilon]\npressure = 0.5\nwind_deg = 10.0");
+        let now = Local.ymd(2026, 10, 14).and_hms(12, 0, 0);
+        let last = LastDrawn::new(&config, test_data(now), now);
+        let with = |change: &dyn Fn(&mut Data)| {
+            let mut data = test_data(now);
+            change(&mut data);
+            changed(
+                &last,
+                &LastDrawn::new(&config, data, now),
+                &config.change_epsilon,
+            )
+        };
+        assert!(!with(&|d| d.indoor[0].pressure = Some(1013.5)));
+        assert!(with(&|d| d.indoor[0].pressure = Some(1013.75)));
+        assert!(with(&|d| d.indoor[0].dewpoint = Some(7.6)));
+        assert!(!with(
+            &|d| d.forecast.as_mut().unwrap().days[1].wind_deg = 280.0
+        ));
+        assert!(with(
+            &|d| d.forecast.as_mut().unwrap().days[1].wind_deg = 280.5
+        ));
+        // going missing, or coming back, always counts
+        assert!(with(&|d| d.indoor[0].humidity = None));
+        assert!(with(&|d| d.indoor[0].co2 = Some(612.0)));
+        assert!(with(&|d| d.outdoor = None));
+        assert!(with(&|d| d.forecast.as_mut().unwrap().days.truncate(2)));
+    }
+
+    #[test]
+    fn everything_drawn_is_compared() {
+        // only the day in the footer, so hours apart it's still the same
+        let config = test_config("updated_format = \"%a %d\"");
+        let now = Local.ymd(2026, 10, 14).and_hms(12, 0, 0);
+        let with_sun = |now: DateTime<Local>| {
+            let mut data = test_data(now);
+            let outdoor = data.outdoor.as_mut().unwrap();
+            let offset = FixedOffset::east(now.offset().local_minus_utc());
+            outdoor.sunrise = Some(now.date().and_hms(7, 10, 0).with_timezone(&offset));
+            outdoor.sunset = Some(now.date().and_hms(18, 40, 0).with_timezone(&offset));
+            LastDrawn::new(&config, data, now)
+        };
+        let last = with_sun(now);
+        let epsilon = &config.change_epsilon;
+        // "dusk 18:40" turning into "dawn 07:10 tmrw" with the same readings
+        assert!(!changed(
+            &last,
+            &with_sun(now + chrono::Duration::hours(6)),
+            epsilon
+        ));
+        assert!(changed(
+            &last,
+            &with_sun(now + chrono::Duration::hours(7)),
+            epsilon
+        ));
+
+        // the same readings an hour on, by when they're marked stale
+        let fresh = LastDrawn::new(&config, test_data(now), now);
+        let stale = LastDrawn::new(&config, test_data(now), now + chrono::Duration::hours(1));
+        assert!(changed(&fresh, &stale, epsilon));
+
+        let mut current = with_sun(now);
+        current.data.indoor_history.push(20.0);
+        assert!(changed(&last, &current, epsilon));
+
+        let mut current = with_sun(now);
+        current.data.battery = Some(BatteryData {
+            voltage: 3.9,
+            percent: 80.0,
+        });
+        assert!(changed(&last, &current, epsilon));
+
+        // the same keys, with the fingerprint main would set for a changed file
+        let mut redeployed = test_config("updated_format = \"%a %d\"");
+        redeployed.fingerprint = 1;
+        let current = LastDrawn::new(&redeployed, test_data(now), now);
+        assert!(changed(
+            &LastDrawn::new(&config, test_data(now), now),
+            &current,
+            epsilon
+        ));
+    }
+
+    #[test]
+    fn the_newest_sparkline_point_jittering_is_not_a_change() {
+        let config = test_config("[change_epsilon]\ntemp = 0.15");
+        let now = Local.ymd(2026, 10, 14).and_hms(12, 0, 0);
+        let last = LastDrawn::new(&config, test_data(now), now);
+        let newest_by = |by: f64| {
+            let mut data = test_data(now);
+            *data.indoor_history.last_mut().unwrap() += by;
+            LastDrawn::new(&config, data, now)
+        };
+        let epsilon = &config.change_epsilon;
+        assert!(!changed(&last, &newest_by(0.1), epsilon));
+        assert!(!changed(&last, &newest_by(-0.1), epsilon));
+        assert!(changed(&last, &newest_by(0.2), epsilon));
+    }
+
+    #[test]
+    fn config_fingerprint_follows_the_keys() {
+        let load = |test: &str, conf: &str, overrides| {
+            load_conf(test, &format!("{}{}", MINIMAL_CONF, conf), overrides)
+                .unwrap()
+                .fingerprint
+        };
+        let fingerprint = load("fingerprint", "", Vec::new());
+        assert_eq!(
+            load("fingerprint_comment", "# a comment\n", Vec::new()),
+            fingerprint
+        );
+        assert_ne!(
+            load("fingerprint_invert", "invert = true\n", Vec::new()),
+            fingerprint
+        );
+        assert_ne!(
+            load("fingerprint_env", "", vec![env("invert", "true")]),
+            fingerprint
+        );
+    }
+
+    #[test]
+    fn quiet_hours_across_midnight() {
+        let quiet = |hour| is_quiet(hour, 23, 7);
+        assert!(!quiet(22));
+        assert!(quiet(23));
+        assert!(quiet(0));
+        assert!(quiet(6));
+        assert!(!quiet(7));
+        assert!(!quiet(12));
+    }
+
+    #[test]
+    fn quiet_hours_within_a_day() {
+        let quiet = |hour| is_quiet(hour, 1, 5);
+        assert!(!quiet(0));
+        assert!(quiet(1));
+        assert!(quiet(4));
+        assert!(!quiet(5));
+        assert!(!quiet(23));
+    }
+
+    #[test]
+    fn quiet_hours_starting_when_they_end_are_never_quiet() {
+        assert!((0..24).all(|hour| !is_quiet(hour, 7, 7)));
+        assert!((0..24).all(|hour| !is_quiet(hour, 0, 0)));
+    }
+
+    #[test]
+    fn cached_weather_expires_after_max_age() {
+        let path = temp_dir("cached_weather").join("weather.json");
+        let home = test_config("").home();
+        let response = serde_json::json!({"current": {"temp": 12.5}});
+        let fetched = utc(2026, 10, 14, 12, 0);
+        save_cached_weather(&path, &home, &response, fetched).unwrap();
+        let max_age = chrono::Duration::minutes(30);
+        let load = |now| load_cached_weather(&path, &home, max_age, now);
+        assert_eq!(load(fetched), Some(response.clone()));
+        assert_eq!(load(utc(2026, 10, 14, 12, 30)), Some(response));
+        assert_eq!(load(utc(2026, 10, 14, 12, 31)), None);
+    }
+
+    #[test]
+    fn sun_phase_through_the_day() {
+        let tz = FixedOffset::west(4 * 3600);
+        let sunrise = tz.ymd(2026, 10, 14).and_hms(7, 12, 0);
+        let sunset = tz.ymd(2026, 10, 14).and_hms(18, 20, 0);
+        let at = |h, m| sun_phase(tz.ymd(2026, 10, 14).and_hms(h, m, 0), sunrise, sunset);
+        assert_eq!(at(5, 0), SunPhase::BeforeSunrise);
+        assert_eq!(at(7, 11), SunPhase::BeforeSunrise);
+        assert_eq!(at(7, 12), SunPhase::Day);
+        assert_eq!(at(18, 19), SunPhase::Day);
+        assert_eq!(at(18, 20), SunPhase::AfterSunset);
+        assert_eq!(at(23, 0), SunPhase::AfterSunset);
+        // the same instant in another timezone
+        let utc_noon = Utc.ymd(2026, 10, 14).and_hms(16, 0, 0);
+        assert_eq!(sun_phase(utc_noon, sunrise, sunset), SunPhase::Day);
+    }
+
+    #[test]
+    fn sun_text_names_the_next_change() {
+        let tz = FixedOffset::east(0);
+        let outdoor = OutdoorData {
+            temp: None,
+            humidity: None,
+            pressure: None,
+            sunrise: Some(tz.ymd(2026, 10, 14).and_hms(7, 12, 0)),
+            sunset: Some(tz.ymd(2026, 10, 14).and_hms(18, 20, 0)),
+            aqi: None,
+            uvi: None,
+        };
+        let at = |h| {
+            sun_text(
+                &outdoor,
+                Utc.ymd(2026, 10, 14).and_hms(h, 0, 0).with_timezone(&Local),
+            )
+        };
+        assert_eq!(at(5).as_deref(), Some("dawn 07:12"));
+        assert_eq!(at(12).as_deref(), Some("dusk 18:20"));
+        assert_eq!(at(20).as_deref(), Some("dawn 07:12 tmrw"));
+    }
+
+    #[test]
+    fn dewpoint_known_values() {
+        let close = |actual: f64, expected: f64| (actual - expected).abs() < 0.05;
+        assert!(close(dewpoint(20.0, 50.0), 9.26));
+        assert!(close(dewpoint(25.0, 60.0), 16.69));
+        assert!(close(dewpoint(-5.0, 80.0), -7.92));
+        // saturated air is at its dewpoint
+        assert!(close(dewpoint(15.0, 100.0), 15.0));
+    }
+
+    #[test]
+    fn comfort_band_edges_are_ok() {
+        let band = |rh| comfort_band(rh, 30.0, 60.0);
+        assert_eq!(band(29.9), Comfort::Low);
+        assert_eq!(band(30.0), Comfort::Ok);
+        assert_eq!(band(45.0), Comfort::Ok);
+        assert_eq!(band(60.0), Comfort::Ok);
+        assert_eq!(band(60.1), Comfort::High);
+        assert_eq!(band(60.1).label(), "HIGH");
+    }
+
+    // An http server on a free port answering each request with the next of
+    // `responses`, a status code and a body. Joining it gives back each
+    // request line and body it got, stopping early if none come for a while.
+    fn fake_server(responses: Vec<(u16, String)>) -> (String, thread::JoinHandle<Vec<String>>) {
+        use std::io::{BufRead, BufReader, Read, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for (code, body) in responses {
+                let deadline = Instant::now() + Duration::from_secs(5);
+                let mut stream = loop {
+                    match listener.accept() {
+                        Ok((stream, _)) => break stream,
+                        Err(_) if Instant::now() < deadline => {
+                            thread::sleep(Duration::from_millis(5))
+                        }
+                        Err(_) => return requests,
+                    }
+                };
+                stream.set_nonblocking(false).unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request = String::new();
+                reader.read_line(&mut request).unwrap();
+                let mut length = 0;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim().is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = header.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            length = value.trim().parse().unwrap();
+                        }
+                    }
+                }
+                let mut content = vec![0; length];
+                reader.read_exact(&mut content).unwrap();
+                request.push_str(&String::from_utf8(content).unwrap());
+                write!(
+                    stream,
+                    "HTTP/1.1 {} Fake\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    code,
+                    body.len(),
+                    body
+                )
+                .unwrap();
+                requests.push(request);
+            }
+            requests
+        });
+        (url, server)
+    }
+
+    // percent decoded, to check the query strings sent
+    fn decoded(request: &str) -> String {
+        url::form_urlencoded::parse(request.as_bytes())
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect::<Vec<_>>()
+            .join("&")
+    }
+
+    // a onecall response for the days from `now` at UTC-4
+    fn onecall(now: DateTime<Local>) -> String {
+        let midday = now.date().and_hms(12, 0, 0).timestamp();
+        let days: Vec<_> = (0..4)
+            .map(|i| {
+                serde_json::json!({
+                    "dt": midday + i * 86400,
+                    "temp": {"max": 14.5 + i as f64, "min": 2.0},
+                    "weather": [{"id": 500, "main": "Rain"}],
+                    "pop": 0.7,
+                    "wind_speed": 3.0,
+                    "wind_deg": 200,
+                    "moon_phase": 0.5,
+                })
+            })
+            .collect();
+        serde_json::json!({
+            "timezone_offset": -4 * 3600,
+            "current": {
+                "dt": now.timestamp(),
+                "temp": 8.5,
+                "feels_like": 6.0,
+                "humidity": 71,
+                "pressure": 1009,
+                "uvi": 2.2,
+            },
+            "daily": days,
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn openweather_from_a_fake_server() {
+        let (url, server) = fake_server(vec![(200, onecall(Local::now()))]);
+        let config = test_config(&format!("openweather_base_url = \"{}\"\nretries = 0", url));
+        let agent = http_agent(&config);
+        let mut openweather = OpenWeather::new(&config, &agent, Local::now());
+        openweather.cache_dir = temp_dir("openweather_happy");
+        let outdoor = openweather.outdoor().unwrap();
+        assert_eq!(outdoor.temp, Some(6.0));
+        assert_eq!(outdoor.humidity, Some(71.0));
+        assert_eq!(outdoor.uvi, Some(2.2));
+        let forecast = openweather.forecast().unwrap();
+        assert_eq!(forecast.days.len(), FORECAST_DAYS);
+        assert_eq!(forecast.days[0].condition, "Rain");
+        // one request for both
+        let requests = server.join().unwrap();
+        assert_eq!(requests.len(), 1);
+        let request = decoded(&requests[0]);
+        assert!(
+            requests[0].starts_with("GET /data/3.0/onecall?"),
+            "{}",
+            requests[0]
+        );
+        assert!(
+            request.contains("lat=45.42&lon=-75.69&appid=key&units=metric"),
+            "{}",
+            request
+        );
+        assert!(
+            request.contains("exclude=minutely,hourly,alerts"),
+            "{}",
+            request
+        );
+        assert!(openweather.cache_path().exists());
+    }
+
+    #[test]
+    fn openweather_malformed_body() {
+        let (url, server) = fake_server(vec![(200, "{\"current\": ".to_string())]);
+        let config = test_config(&format!("openweather_base_url = \"{}\"\nretries = 0", url));
+        let agent = http_agent(&config);
+        let mut openweather = OpenWeather::new(&config, &agent, Local::now());
+        openweather.cache_dir = temp_dir("openweather_malformed");
+        assert!(openweather.outdoor().is_err());
+        assert_eq!(server.join().unwrap().len(), 1);
+        assert!(!openweather.cache_path().exists());
+    }
+
+    #[test]
+    fn openweather_non_200() {
+        let body = "{\"cod\":401,\"message\":\"Invalid API key\"}".to_string();
+        let (url, server) = fake_server(vec![(401, body)]);
+        let config = test_config(&format!("openweather_base_url = \"{}\"\nretries = 0", url));
+        let agent = http_agent(&config);
+        let mut openweather = OpenWeather::new(&config, &agent, Local::now());
+        openweather.cache_dir = temp_dir("openweather_401");
+        match openweather.outdoor() {
+            Err(Oops::Status { service, code, .. }) => {
+                assert_eq!((service.as_str(), code), ("OpenWeather", 401))
+            }
+            Err(e) => panic!("{}", e),
+            Ok(_) => panic!("a 401 should fail"),
+        }
+        assert_eq!(server.join().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn openweather_failure_falls_back_on_the_cache() {
+        let (url, server) = fake_server(vec![(503, String::new())]);
+        let config = test_config(&format!("openweather_base_url = \"{}\"\nretries = 0", url));
+        let agent = http_agent(&config);
+        let now = Local::now();
+        let mut openweather = OpenWeather::new(&config, &agent, now);
+        openweather.cache_dir = temp_dir("openweather_cached");
+        let cached: serde_json::Value = serde_json::from_str(&onecall(now)).unwrap();
+        let fetched = now.with_timezone(&Utc) - chrono::Duration::minutes(10);
+        save_cached_weather(&openweather.cache_path(), &config.home(), &cached, fetched).unwrap();
+        assert_eq!(openweather.outdoor().unwrap().temp, Some(6.0));
+        server.join().unwrap();
+    }
+
+    // an InfluxQL response with one row of `columns` and `values`
+    fn influxql(columns: &str, values: &str) -> String {
+        format!(
+            "{{\"results\":[{{\"statement_id\":0,\"series\":[{{\"name\":\"indoor\",\"columns\":[{}],\"values\":[[{}]]}}]}}]}}",
+            columns, values
+        )
+    }
+
+    #[test]
+    fn influx_from_a_fake_server() {
+        let body = influxql(
+            "\"time\",\"temperature\",\"pressure\",\"humidity\"",
+            "\"2026-10-14T12:00:00Z\",21.5,1012.5,45",
+        );
+        let (url, server) = fake_server(vec![(200, body)]);
+        let mut config = test_config("retries = 0");
+        config.influx_server = format!("{}/query", url);
+        config.influx_database = "home".to_string();
+        let agent = influx_agent(&config).unwrap();
+        let indoor = InfluxDb::new(&config, &agent).indoor().unwrap();
+        assert_eq!(indoor.len(), 1);
+        assert_eq!(indoor[0].label, "indoor");
+        assert_eq!(indoor[0].temp, Some(21.5));
+        assert_eq!(indoor[0].pressure, Some(1012.5));
+        assert_eq!(indoor[0].sampled_at, Some(utc(2026, 10, 14, 12, 0)));
+        let request = decoded(&server.join().unwrap()[0]);
+        assert!(
+            request.contains("db=home&q=SELECT MEAN(\"temperature\")"),
+            "{}",
+            request
+        );
+    }
+
+    #[test]
+    fn influx_malformed_body() {
+        let (url, server) = fake_server(vec![(200, "<html>proxy error</html>".to_string())]);
+        let mut config = test_config("retries = 0");
+        config.influx_server = format!("{}/query", url);
+        let agent = influx_agent(&config).unwrap();
+        let error = InfluxDb::new(&config, &agent).indoor().err().unwrap();
+        assert!(matches!(error, Oops::Json(_)), "{}", error);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn influx_non_200() {
+        let body = "{\"error\":\"authorization failed\"}".to_string();
+        let (url, server) = fake_server(vec![(401, body)]);
+        let mut config = test_config("retries = 0");
+        config.influx_server = format!("{}/query", url);
+        let agent = influx_agent(&config).unwrap();
+        let error = InfluxDb::new(&config, &agent).indoor().err().unwrap();
+        assert!(matches!(error, Oops::Status { code: 401, .. }), "{}", error);
+        assert_eq!(error.exit_code(), 3);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn a_new_footer_is_a_change() {
+        let now = Local.ymd(2026, 10, 14).and_hms(12, 0, 0);
+        let later = now + chrono::Duration::minutes(5);
+        let changed_by = |config: &Config| {
+            let last = LastDrawn::new(config, test_data(now), now);
+            let current = LastDrawn::new(config, test_data(later), later);
+            changed(&last, &current, &config.change_epsilon)
+        };
+        assert!(changed_by(&test_config("")));
+        // the same readings on the same day, with only the day shown
+        assert!(!changed_by(&test_config("updated_format = \"%a %d\"")));
+    }
+
+    #[test]
+    fn prometheus_exposition_format() {
+        let path = temp_dir("prometheus").join("malter.prom");
+        let now = Local.ymd(2026, 10, 14).and_hms(12, 0, 0);
+        let mut data = test_data(now);
+        data.indoor[0].label = "Kid's \"big\" room".to_string();
+        data.indoor[0].humidity = None;
+        data.outdoor.as_mut().unwrap().uvi = None;
+        data.forecast = None;
+        write_prometheus(&path, &data, Units::Metric).unwrap();
+        let room = "{room=\"Kid's \\\"big\\\" room\"}";
+        let expected = format!(
+            "# TYPE malter_indoor_temp_celsius gauge\n\
+             malter_indoor_temp_celsius{room} 21.5\n\
+             # TYPE malter_indoor_pressure_hpa gauge\n\
+             malter_indoor_pressure_hpa{room} 1013\n\
+             # TYPE malter_indoor_dewpoint_celsius gauge\n\
+             malter_indoor_dewpoint_celsius{room} 7.5\n\
+             # TYPE malter_outdoor_temp_celsius gauge\n\
+             malter_outdoor_temp_celsius -12.3\n\
+             # TYPE malter_outdoor_humidity_percent gauge\n\
+             malter_outdoor_humidity_percent 80\n\
+             # TYPE malter_outdoor_pressure_hpa gauge\n\
+             malter_outdoor_pressure_hpa 1001\n",
+            room = room
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap(), expected);
+        assert!(!path.with_extension("prom.tmp").exists());
+    }
+
+    #[test]
+    fn prometheus_rooms_share_a_type_line() {
+        let path = temp_dir("prometheus_rooms").join("malter.prom");
+        let now = Local.ymd(2026, 10, 14).and_hms(12, 0, 0);
+        let mut data = test_data(now);
+        let mut bedroom = test_data(now).indoor.remove(0);
+        bedroom.label = "bedroom".to_string();
+        bedroom.temp = Some(18.0);
+        data.indoor.push(bedroom);
+        write_prometheus(&path, &data, Units::Imperial).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            text.matches("# TYPE malter_indoor_temp_fahrenheit gauge\n")
+                .count(),
+            1
+        );
+        assert!(text.contains(
+            "# TYPE malter_indoor_temp_fahrenheit gauge\n\
+             malter_indoor_temp_fahrenheit{room=\"indoor\"} 21.5\n\
+             malter_indoor_temp_fahrenheit{room=\"bedroom\"} 18\n"
+        ));
+        assert!(text.contains("malter_forecast_pop_ratio 0.4\n"));
+    }
+
+    #[test]
+    fn influx_auth_prefers_a_token() {
+        let authorization = |extra: &str| {
+            let config = test_config(extra);
+            let request = influx_auth(ureq::get("http://localhost:8086/query"), &config);
+            request.header("Authorization").map(str::to_string)
+        };
+        assert_eq!(authorization(""), None);
+        // "malter:secret"
+        assert_eq!(
+            authorization("influx_user = \"malter\"\ninflux_password = \"secret\"").as_deref(),
+            Some("Basic bWFsdGVyOnNlY3JldA==")
+        );
+        assert_eq!(
+            authorization("influx_user = \"malter\"").as_deref(),
+            Some("Basic bWFsdGVyOg==")
+        );
+        assert_eq!(
+            authorization(
+                "influx_token = \"abc\"\ninflux_user = \"malter\"\ninflux_password = \"secret\""
+            )
+            .as_deref(),
+            Some("Token abc")
+        );
+        // a password alone isn't enough for basic auth
+        assert_eq!(authorization("influx_password = \"secret\""), None);
+    }
+
+    // two tables, as a union of differently aggregated fields gives back, with
+    // the annotations the v2 api adds to each
+    const FLUX_TWO_TABLES: &str = "#group,false,false,true,true,false,false,false\r\n\
+#datatype,string,long,dateTime:RFC3339,dateTime:RFC3339,dateTime:RFC3339,double,double\r\n\
+#default,_result,,,,,,\r\n\
+,result,table,_start,_stop,_time,humidity,temperature\r\n\
+,,0,2026-10-13T12:00:00Z,2026-10-14T12:00:00Z,2026-10-14T11:45:00Z,41.5,21.25\r\n\
+\r\n\
+#group,false,false,true,true,false,false\r\n\
+#datatype,string,long,dateTime:RFC3339,dateTime:RFC3339,dateTime:RFC3339,double\r\n\
+#default,_result,,,,,\r\n\
+,result,table,_start,_stop,_time,pressure\r\n\
+,,1,2026-10-13T12:00:00Z,2026-10-14T12:00:00Z,2026-10-14T11:30:00Z,1013.5\r\n\
+\r\n";
+
+    #[test]
+    fn flux_csv_with_several_tables() {
+        let records = parse_flux_csv(FLUX_TWO_TABLES).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["temperature"], "21.25");
+        assert_eq!(records[0]["table"], "0");
+        assert_eq!(records[1]["pressure"], "1013.5");
+        assert!(!records[1].contains_key("temperature"));
+        // the newest row wins, the older one's fields aren't mixed in
+        let row = Flux.parse_latest(FLUX_TWO_TABLES).unwrap().unwrap();
+        assert_eq!(row.sampled_at, Some(utc(2026, 10, 14, 11, 45)));
+        assert_eq!(row.temp, Some(21.25));
+        assert_eq!(row.pressure, None);
+    }
+
+    #[test]
+    fn flux_csv_empty_result() {
+        for body in ["", "\r\n", "\r\n\r\n"] {
+            assert!(parse_flux_csv(body).unwrap().is_empty());
+            assert!(Flux.parse_latest(body).unwrap().is_none());
+            assert!(Flux.parse_history(body).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn flux_csv_quoting_and_errors() {
+        let body = "#datatype,string,long,string\n,result,table,room\n,_result,0,\"Kid's, \"\"big\"\" room\"\n";
+        assert_eq!(
+            parse_flux_csv(body).unwrap()[0]["room"],
+            "Kid's, \"big\" room"
+        );
+        let body = "#datatype,string,string\n,error,reference\n,\"compilation failed: undefined identifier nope\",\n";
+        let error = parse_flux_csv(body).unwrap_err().to_string();
+        assert!(error.contains("undefined identifier nope"));
+    }
+
+    #[test]
+    fn flux_history_is_the_values_in_order() {
+        let body = "#datatype,string,long,dateTime:RFC3339,double\n,result,table,_time,_value\n\
+,_result,0,2026-10-14T10:00:00Z,20.5\n,_result,0,2026-10-14T10:15:00Z,20.75\n";
+        assert_eq!(Flux.parse_history(body).unwrap(), vec![20.5, 20.75]);
+    }
+
+    #[test]
+    fn precision_sets_the_decimal_places() {
+        let config = test_config("");
+        let units = config.units;
+        assert_eq!(units.format_temp(21.04, config.precision.temp), "21.0C");
+        assert_eq!(
+            units.format_pressure(1013.24, config.precision.pressure),
+            "1013 hPa"
+        );
+
+        let config = test_config("[precision]\ntemp = 0\nhumidity = 0\npressure = 1");
+        assert_eq!(units.format_temp(21.04, config.precision.temp), "21C");
+        assert_eq!(units.format_temp_delta(2.2, config.precision.temp), "+2C");
+        assert_eq!(
+            units.format_pressure(1013.24, config.precision.pressure),
+            "1013.2 hPa"
+        );
+        assert_eq!(
+            Units::Imperial.format_pressure(29.9212, config.precision.pressure),
+            "29.9 inHg"
+        );
+    }
+
+    fn test_row(temp: f64, humidity: f64, pressure: f64) -> IndoorRow {
+        IndoorRow::from_fields(None, |field| match field {
+            "temperature" => Some(temp),
+            "humidity" => Some(humidity),
+            "pressure" => Some(pressure),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn calibration_offsets_are_added_to_the_readings() {
+        let config = test_config(
+            "indoor_temp_offset = -1.5\nindoor_humidity_offset = 4.0\nindoor_pressure_offset = 2.5",
+        );
+        let row = test_row(22.0, 40.0, 1010.0).calibrated(&config);
+        assert_eq!(row.temp, Some(20.5));
+        assert_eq!(row.humidity, Some(44.0));
+        assert_eq!(row.pressure, Some(1012.5));
+        // left alone when they weren't read
+        assert_eq!(row.co2, None);
+
+        // humidity stays a percentage
+        assert_eq!(
+            test_row(22.0, 98.0, 1010.0).calibrated(&config).humidity,
+            Some(100.0)
+        );
+
+        // the offset's in celsius, before converting to fahrenheit
+        let config = test_config("units = \"imperial\"\nindoor_temp_offset = -5.0");
+        let indoor = indoor_data(
+            "indoor",
+            test_row(25.0, 40.0, 1010.0).calibrated(&config),
+            config.units,
+        );
+        assert_eq!(indoor.temp, Some(68.0));
+    }
+
+    #[test]
+    fn font_shrinks_only_when_the_text_doesnt_fit() {
+        let big = FontChoice::Font24x32;
+        assert_eq!(big.text_size("-12.3C"), Size::new(144, 32));
+        assert_eq!(big.text_size("12\n1013"), Size::new(96, 64));
+        assert_eq!(big.text_size(""), Size::new(0, 0));
+
+        // right at the edge still fits, a character more doesn't
+        assert_eq!(big.fitting("12.3C", Size::new(120, 32)), big);
+        assert_eq!(
+            big.fitting("-12.3C", Size::new(120, 32)),
+            FontChoice::Font12x16
+        );
+        assert_eq!(
+            big.fitting("12.3C", Size::new(120, 31)),
+            FontChoice::Font12x16
+        );
+        // 8x16 is a pixel too wide for ten characters, 6x12 isn't
+        assert_eq!(
+            big.fitting("1013.2 hPa", Size::new(79, 32)),
+            FontChoice::Font6x12
+        );
+        // the smallest when nothing fits
+        assert_eq!(
+            big.fitting("1013.2 hPa", Size::new(20, 32)),
+            FontChoice::Font6x8
+        );
+        // and never gets bigger than asked for
+        let small = FontChoice::Font8x16;
+        assert_eq!(small.fitting("21C", Size::new(200, 100)), small);
+    }
+
+    #[test]
+    fn openweather_forecast_starts_on_the_locations_today() {
+        // midday at UTC-4 from the 13th on, each day a degree warmer
+        let daily: Vec<_> = (0..5)
+            .map(|i| {
+                serde_json::json!({
+                    "dt": utc(2026, 10, 13 + i, 16, 0).timestamp(),
+                    "temp": {"max": 10.0 + i as f64, "min": 0.0},
+                    "moon_phase": 0.1 * i as f64,
+                })
+            })
+            .collect();
+        let response = serde_json::json!({"timezone_offset": -4 * 3600, "daily": daily});
+        let config = test_config("");
+        let agent = http_agent(&config);
+        let forecast = |now: DateTime<Utc>| {
+            let openweather = OpenWeather::new(&config, &agent, now.with_timezone(&Local));
+            openweather.response.set(response.clone()).unwrap();
+            openweather.forecast().unwrap()
+        };
+
+        // the 14th in UTC but still the 13th there, so that's kept
+        let forecast_13th = forecast(utc(2026, 10, 14, 3, 0));
+        let highs: Vec<f64> = forecast_13th.days.iter().map(|day| day.high).collect();
+        assert_eq!(highs, [10.0, 11.0, 12.0]);
+        assert_eq!(forecast_13th.moon_phase, Some(0.0));
+
+        // and once it's the 14th there too the 13th is over
+        let forecast_14th = forecast(utc(2026, 10, 14, 5, 0));
+        assert_eq!(
+            forecast_14th.days[0].date,
+            NaiveDate::from_ymd_opt(2026, 10, 14)
+        );
+        assert_eq!(forecast_14th.days[0].high, 11.0);
+        assert_eq!(forecast_14th.moon_phase, Some(0.1));
+    }
+
+    // Checks `config` drawing `data` against tests/snapshots/<name>.txt, the
+    // frame drawn as run draws it and put through buffer_to_ascii. Running the
+    // tests with UPDATE_SNAPSHOTS=1 writes them instead, look over the diff
+    // before committing one.
+    fn assert_snapshot(name: &str, config: &Config, data: &Data, now: DateTime<Local>) {
+        let frame = render(config, data, now).unwrap();
+        let (width, height) = config.panel.size();
+        let actual = buffer_to_ascii(&frame, width, height, config.rotation());
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/snapshots")
+            .join(format!("{}.txt", name));
+        if env::var_os("UPDATE_SNAPSHOTS").is_some() {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, &actual).unwrap();
+            return;
+        }
+        let expected = fs::read_to_string(&path)
+            .unwrap_or_else(|e| panic!("{}: {}, UPDATE_SNAPSHOTS=1 makes it", path.display(), e));
+        assert!(
+            actual == expected,
+            "the frame isn't {} any more, UPDATE_SNAPSHOTS=1 if that's on purpose:\n{}",
+            path.display(),
+            actual
+        );
+    }
+    // test_data with the sun times and a fixed time to draw it at
+    fn snapshot_data() -> (Data, DateTime<Local>) {
+        let now = Local.ymd(2026, 10, 14).and_hms(12, 0, 0);
+        let mut data = test_data(now);
+        let outdoor = data.outdoor.as_mut().unwrap();
+        // local, so noon is between them whatever TZ the tests run in
+        let there = now.offset().fix();
+        outdoor.sunrise = Some(there.ymd(2026, 10, 14).and_hms(7, 12, 0));
+        outdoor.sunset = Some(there.ymd(2026, 10, 14).and_hms(18, 9, 0));
+        (data, now)
+    }
+
+    #[test]
+    fn snapshot_three_column() {
+        let (data, now) = snapshot_data();
+        assert_snapshot("three_column", &test_config(""), &data, now);
+    }
+
+    #[test]
+    fn snapshot_missing_sources() {
+        let (mut data, now) = snapshot_data();
+        data.indoor.clear();
+        data.outdoor = None;
+        data.forecast = None;
+        data.indoor_history.clear();
+        assert_snapshot("missing_sources", &test_config(""), &data, now);
+    }
+
+    #[test]
+    fn snapshot_imperial_7in5() {
+        let (data, now) = snapshot_data();
+        let config = test_config("units = \"imperial\"\npanel = \"7in5\"\nshow_uvi = true");
+        assert_snapshot("imperial_7in5", &config, &data, now);
+    }
+
+    #[test]
+    fn co2_and_voc_are_only_read_when_asked_for() {
+        let config = test_config("");
+        let source = &config.indoor_sources[0];
+        assert_eq!(
+            indoor_fields(&config),
+            ["temperature", "pressure", "humidity"]
+        );
+        assert!(!InfluxQl.latest_query(&config, source).contains("co2"));
+
+        let config = test_config("indoor_co2 = true\nindoor_voc = true");
+        let source = &config.indoor_sources[0];
+        assert_eq!(
+            indoor_fields(&config),
+            ["temperature", "pressure", "humidity", "co2", "voc"]
+        );
+        let query = InfluxQl.latest_query(&config, source);
+        assert!(
+            query.contains("MEAN(\"co2\") as \"co2\", MEAN(\"voc\") as \"voc\""),
+            "{}",
+            query
+        );
+
+        // a response without the columns just leaves them None
+        let without = influxql(
+            "\"time\",\"temperature\",\"pressure\",\"humidity\"",
+            "\"2026-10-14T12:00:00Z\",21.5,1012.5,45",
+        );
+        let row = InfluxQl.parse_latest(&without).unwrap().unwrap();
+        assert_eq!((row.temp, row.co2, row.voc), (Some(21.5), None, None));
+
+        let with = influxql(
+            "\"time\",\"temperature\",\"pressure\",\"humidity\",\"co2\",\"voc\"",
+            "\"2026-10-14T12:00:00Z\",21.5,1012.5,45,612,null",
+        );
+        let row = InfluxQl.parse_latest(&with).unwrap().unwrap();
+        assert_eq!(
+            (row.temp, row.co2, row.voc),
+            (Some(21.5), Some(612.0), None)
+        );
+        let indoor = indoor_data("indoor", row, config.units);
+        assert_eq!(indoor.co2, Some(612.0));
+    }
+
+    // Config::load_with on `conf` written to a file of its own
+    fn load_conf(test: &str, conf: &str, overrides: Vec<(String, toml::Value)>) -> Result<Config> {
+        let path = temp_dir(test).join("conf.toml");
+        fs::write(&path, conf).unwrap();
+        Config::load_with(path.to_str().unwrap(), overrides)
+    }
+
+    const MINIMAL_CONF: &str = "lat = 45.42\nlon = -75.69\nopenweather_api_key = \"key\"\n";
+
+    #[test]
+    fn a_misspelt_key_is_named() {
+        let misspelt = |test: &str, conf: &str| {
+            let error = load_conf(test, &format!("{}{}", MINIMAL_CONF, conf), Vec::new())
+                .err()
+                .unwrap();
+            assert_eq!(error.exit_code(), 2);
+            error.to_string()
+        };
+        let msg = misspelt("misspelt_top", "infux_server = \"http://pi:8086/query\"\n");
+        assert!(
+            msg.contains("has an unknown key \"infux_server\", check it for typos"),
+            "{}",
+            msg
+        );
+        assert!(msg.contains("misspelt_top/conf.toml"), "{}", msg);
+        // not serde's list of every key it could have been
+        assert!(!msg.contains("expected one of"), "{}", msg);
+
+        let msg = misspelt("misspelt_pins", "[pins]\nbsuy = 24\n");
+        assert!(msg.contains("unknown key \"bsuy\""), "{}", msg);
+        let msg = misspelt(
+            "misspelt_source",
+            "[[indoor_sources]]\nname = \"den\"\nmeasurment = \"den\"\n",
+        );
+        assert!(msg.contains("unknown key \"measurment\""), "{}", msg);
+
+        // anything else wrong is left as toml had it
+        let msg = misspelt("wrong_type", "retries = \"three\"\n");
+        assert!(!msg.contains("unknown key"), "{}", msg);
+
+        // one merged in from the secrets file could be in either
+        let dir = temp_dir("unknown_secret");
+        fs::write(dir.join("secrets.toml"), "openweather_key = \"key\"\n").unwrap();
+        fs::write(
+            dir.join("conf.toml"),
+            format!("{}secrets_file = \"secrets.toml\"\n", MINIMAL_CONF),
+        )
+        .unwrap();
+        let msg = Config::load_with(dir.join("conf.toml").to_str().unwrap(), Vec::new())
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(msg.contains("conf.toml or "), "{}", msg);
+        assert!(
+            msg.contains("secrets.toml has an unknown key \"openweather_key\""),
+            "{}",
+            msg
+        );
+    }
+
+    // MALTER_<KEY>=value as env_overrides would read it
+    fn env(key: &str, value: &str) -> (String, toml::Value) {
+        (key.to_string(), env_value(key, value.to_string()))
+    }
+
+    #[test]
+    fn env_wins_over_the_file_and_secrets() {
+        let dir = temp_dir("env_wins");
+        let path = dir.join("conf.toml");
+        fs::write(
+            &path,
+            format!(
+                "{}openweather_api_key = \"from file\"\nsecrets_file = \"secrets.toml\"\nretries = 1\n",
+                MINIMAL_CONF.replace("openweather_api_key = \"key\"\n", "")
+            ),
+        )
+        .unwrap();
+        fs::write(
+            dir.join("secrets.toml"),
+            "openweather_api_key = \"from secrets\"\ninflux_password = \"hunter2\"\n",
+        )
+        .unwrap();
+        let config = Config::load_with(
+            path.to_str().unwrap(),
+            vec![
+                env("openweather_api_key", "0123456789"),
+                env("influx_password", "123456"),
+                env("retries", "5"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(config.openweather_api_key, "0123456789");
+        assert_eq!(config.influx_password.as_deref(), Some("123456"));
+        assert_eq!(config.retries, 5);
+    }
+
+    #[test]
+    fn env_values_are_typed_by_key() {
+        assert_eq!(
+            env_value("influx_password", "123456".to_string()),
+            toml::Value::String("123456".to_string())
+        );
+        assert_eq!(
+            env_value("openweather_api_version", "2.5".to_string()),
+            toml::Value::String("2.5".to_string())
+        );
+        assert_eq!(
+            env_value("retries", "5".to_string()),
+            toml::Value::Integer(5)
+        );
+        assert_eq!(
+            env_value("invert", "true".to_string()),
+            toml::Value::Boolean(true)
+        );
+        // not TOML at all, left for the key's own type to reject
+        assert_eq!(
+            env_value("retries", "five".to_string()),
+            toml::Value::String("five".to_string())
+        );
+    }
+
+    #[test]
+    fn unknown_keys_from_the_environment_name_the_variable() {
+        let msg = load_conf("unknown_env", MINIMAL_CONF, vec![env("retires", "5")])
+            .err()
+            .unwrap()
+            .to_string();
+        assert_eq!(
+            msg,
+            "MALTER_RETIRES is set but \"retires\" isn't a config key"
+        );
+    }
+
+    #[test]
+    fn pressure_trend_around_the_threshold() {
+        assert_eq!(pressure_trend(1014.5, 1013.0, 1.0), Trend::Rising);
+        assert_eq!(pressure_trend(1014.0, 1013.0, 1.0), Trend::Steady);
+        assert_eq!(pressure_trend(1013.0, 1013.0, 1.0), Trend::Steady);
+        assert_eq!(pressure_trend(1012.0, 1013.0, 1.0), Trend::Steady);
+        assert_eq!(pressure_trend(1011.5, 1013.0, 1.0), Trend::Falling);
+    }
+
+    #[test]
+    fn pressure_trend_off_makes_no_query() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let mut config = test_config("pressure_trend_hpa = 0");
+        config.influx_server = format!("http://{}/query", listener.local_addr().unwrap());
+        let agent = influx_agent(&config).unwrap();
+        let data = test_data(Local::now());
+        assert_eq!(get_pressure_trend(&agent, &config, &data), None);
+        assert!(listener.accept().is_err());
+    }
+
+    #[test]
+    fn call_count_rolls_over_with_the_utc_day() {
+        let path = temp_dir("call_count_rollover").join("calls.json");
+        let today = NaiveDate::from_ymd(2026, 10, 14);
+        let tomorrow = today.succ();
+        save_call_count(&path, today, 5).unwrap();
+        assert!(!under_rate_limit(&path, 5, today));
+        assert_eq!(calls_on(&path, tomorrow), 0);
+        assert!(under_rate_limit(&path, 5, tomorrow));
+        // and the first call of the new day starts the count again
+        save_call_count(&path, tomorrow, calls_on(&path, tomorrow) + 1).unwrap();
+        assert_eq!(calls_on(&path, tomorrow), 1);
+        assert_eq!(calls_on(&path, today), 0);
+    }
+
+    #[test]
+    fn missing_or_broken_call_count_is_no_calls() {
+        let dir = temp_dir("call_count_broken");
+        let today = NaiveDate::from_ymd(2026, 10, 14);
+        assert!(under_rate_limit(&dir.join("missing.json"), 1, today));
+        fs::write(dir.join("broken.json"), "{\"day\":").unwrap();
+        assert_eq!(calls_on(&dir.join("broken.json"), today), 0);
+        // a cap of 0 allows nothing at all
+        assert!(!under_rate_limit(&dir.join("missing.json"), 0, today));
+    }
+
+    #[test]
+    fn rate_limit_stops_at_the_cap() {
+        let path = temp_dir("rate_limit_cap").join("calls.json");
+        let today = NaiveDate::from_ymd(2026, 10, 14);
+        let cap = 3;
+        for calls in 0..cap {
+            assert!(under_rate_limit(&path, cap, today));
+            save_call_count(&path, today, calls_on(&path, today) + 1).unwrap();
+            assert_eq!(calls_on(&path, today), calls + 1);
+        }
+        assert!(!under_rate_limit(&path, cap, today));
+        assert!(under_rate_limit(&path, cap + 1, today));
+    }
+
+    #[test]
+    fn moon_phase_quarters() {
+        assert_eq!(moon_phase_name(0.0), "New Moon");
+        assert_eq!(moon_phase_name(0.25), "First Quarter");
+        assert_eq!(moon_phase_name(0.5), "Full Moon");
+        assert_eq!(moon_phase_name(0.75), "Last Quarter");
+        assert_eq!(moon_phase_name(1.0), "New Moon");
+    }
+
+    #[test]
+    fn moon_phase_names_cover_the_eighth_around_them() {
+        assert_eq!(moon_phase_name(0.06), "New Moon");
+        assert_eq!(moon_phase_name(0.0625), "Waxing Crescent");
+        assert_eq!(moon_phase_name(0.4375), "Full Moon");
+        assert_eq!(moon_phase_name(0.9375), "New Moon");
+        assert_eq!(moon_phase_name(0.93), "Waning Crescent");
+    }
+
+    #[test]
+    fn header_names_the_moon_phase_when_asked() {
+        let now = Local.ymd(2026, 10, 14).and_hms(12, 0, 0);
+        let mut data = test_data(now);
+        assert_eq!(header_label(&test_config(""), &data, now), "45.42, -75.69");
+
+        let config = test_config("show_moon_phase = true");
+        assert_eq!(
+            header_label(&config, &data, now),
+            "45.42, -75.69 | First Quarter"
+        );
+        data.forecast.as_mut().unwrap().moon_phase = Some(0.5);
+        assert_eq!(
+            header_label(&config, &data, now),
+            "45.42, -75.69 | Full Moon"
+        );
+
+        // Open-Meteo has no phase, and there's none without a forecast
+        data.forecast.as_mut().unwrap().moon_phase = None;
+        assert_eq!(header_label(&config, &data, now), "45.42, -75.69");
+        data.forecast = None;
+        assert_eq!(header_label(&config, &data, now), "45.42, -75.69");
+    }
+
+    #[test]
+    fn only_a_new_moon_phase_name_is_a_change() {
+        let config = test_config("show_moon_phase = true");
+        let now = Local.ymd(2026, 10, 14).and_hms(12, 0, 0);
+        let last = LastDrawn::new(&config, test_data(now), now);
+        let with_phase = |phase| {
+            let mut data = test_data(now);
+            data.forecast.as_mut().unwrap().moon_phase = Some(phase);
+            changed(
+                &last,
+                &LastDrawn::new(&config, data, now),
+                &config.change_epsilon,
+            )
+        };
+        // still the first quarter
+        assert!(!with_phase(0.31));
+        assert!(with_phase(0.5));
+    }
+
+    #[test]
+    fn queries_use_the_configured_aggregations() {
+        let aggregation = "[aggregation]\ntemperature = \"last\"\npressure = \"median\"\n";
+        let config = test_config(aggregation);
+        let source = &config.indoor_sources[0];
+        let query = InfluxQl.latest_query(&config, source);
+        for column in [
+            "LAST(\"temperature\") as \"temperature\"",
+            "MEDIAN(\"pressure\") as \"pressure\"",
+            "MEAN(\"humidity\") as \"humidity\"",
+        ] {
+            assert!(query.contains(column), "{}", query);
+        }
+        let history = InfluxQl.history_query(&config, source, "temperature", 24);
+        assert!(
+            history.starts_with("SELECT LAST(\"temperature\")"),
+            "{}",
+            history
+        );
+
+        // Flux gets a stream per function, unioned back together
+        let config = test_config(&format!(
+            "influx_flux = true\ninflux_org = \"home\"\n{}",
+            aggregation
+        ));
+        let source = &config.indoor_sources[0];
+        let query = Flux.latest_query(&config, source);
+        assert!(query.starts_with("union(tables: [\n"), "{}", query);
+        for (fields, function) in [
+            ("r._field == \"temperature\")", "last"),
+            ("r._field == \"pressure\")", "median"),
+            ("r._field == \"humidity\")", "mean"),
+        ] {
+            let stream = query
+                .split("from(bucket:")
+                .find(|stream| stream.contains(fields))
+                .unwrap_or_else(|| panic!("no {} in {}", fields, query));
+            assert!(stream.contains(&format!("fn: {},", function)), "{}", stream);
+        }
+        let history = Flux.history_query(&config, source, "pressure", 24);
+        assert!(history.contains("fn: median,"), "{}", history);
+    }
+
+    #[test]
+    fn rain_banner_is_over_the_threshold_today() {
+        let config = test_config("rain_banner_threshold = 0.6");
+        let mut data = test_data(Local::now());
+        let pop = |data: &mut Data, pop: f64| data.forecast.as_mut().unwrap().days[0].pop = pop;
+        pop(&mut data, 0.59);
+        assert!(!rain_expected(&data, &config));
+        // right on it isn't over
+        pop(&mut data, 0.6);
+        assert!(!rain_expected(&data, &config));
+        pop(&mut data, 0.61);
+        assert!(rain_expected(&data, &config));
+        // and over 1 is never
+        pop(&mut data, 1.0);
+        assert!(!rain_expected(
+            &data,
+            &test_config("rain_banner_threshold = 1.01")
+        ));
+
+        // it's only today's that counts
+        pop(&mut data, 0.0);
+        data.forecast.as_mut().unwrap().days[1].pop = 1.0;
+        assert!(!rain_expected(&data, &config));
+
+        data.forecast.as_mut().unwrap().days.clear();
+        assert!(!rain_expected(&data, &config));
+        data.forecast = None;
+        assert!(!rain_expected(&data, &config));
+    }
+
+    #[test]
+    fn crossing_the_rain_banner_threshold_is_a_change() {
+        let config = test_config("rain_banner_threshold = 0.6\n[change_epsilon]\npop = 0.05");
+        let now = Local.ymd(2026, 10, 14).and_hms(12, 0, 0);
+        let with_pop = |pop| {
+            let mut data = test_data(now);
+            data.forecast.as_mut().unwrap().days[0].pop = pop;
+            LastDrawn::new(&config, data, now)
+        };
+        let epsilon = &config.change_epsilon;
+        assert!(!changed(&with_pop(0.57), &with_pop(0.6), epsilon));
+        // less than the epsilon, but the banner comes up
+        assert!(changed(&with_pop(0.58), &with_pop(0.61), epsilon));
+    }
+
+    #[test]
+    fn later_layers_win_file_then_secrets_then_env() {
+        let dir = temp_dir("layers");
+        fs::write(
+            dir.join("conf.toml"),
+            format!(
+                "{}secrets_file = \"secrets.toml\"\nretries = 1\ninflux_user = \"file\"\ninflux_database = \"file\"\n",
+                MINIMAL_CONF
+            ),
+        )
+        .unwrap();
+        fs::write(
+            dir.join("secrets.toml"),
+            "influx_user = \"secrets\"\ninflux_database = \"secrets\"\n",
+        )
+        .unwrap();
+        fs::write(dir.join("other.toml"), "influx_user = \"other\"\n").unwrap();
+        let path = dir.join("conf.toml");
+        let load = |overrides| Config::load_with(path.to_str().unwrap(), overrides).unwrap();
+
+        let config = load(vec![env("influx_database", "env")]);
+        assert_eq!(config.retries, 1);
+        assert_eq!(config.influx_user.as_deref(), Some("secrets"));
+        assert_eq!(config.influx_database, "env");
+
+        // and secrets_file itself can come from the environment
+        let config = load(vec![env("secrets_file", "other.toml")]);
+        assert_eq!(config.influx_user.as_deref(), Some("other"));
+        assert_eq!(config.influx_database, "file");
+    }
+
+    #[test]
+    fn secrets_replace_whole_tables_from_a_relative_path() {
+        let dir = temp_dir("secrets_tables");
+        fs::create_dir_all(dir.join("conf")).unwrap();
+        fs::write(
+            dir.join("conf/conf.toml"),
+            format!(
+                "{}secrets_file = \"../secrets.toml\"\n[pins]\ncs = 7\nbusy = 5\n",
+                MINIMAL_CONF
+            ),
+        )
+        .unwrap();
+        // relative to conf.toml, not wherever malter's run from
+        fs::write(dir.join("secrets.toml"), "[pins]\ncs = 9\n").unwrap();
+        let path = dir.join("conf/conf.toml");
+        let config = Config::load_with(path.to_str().unwrap(), Vec::new()).unwrap();
+        // busy isn't merged in from conf.toml's [pins], it's the default again
+        assert_eq!((config.pins.cs, config.pins.busy), (9, 24));
+
+        // a missing one names where it was looked for
+        fs::remove_file(dir.join("secrets.toml")).unwrap();
+        let error = Config::load_with(path.to_str().unwrap(), Vec::new())
+            .err()
+            .unwrap();
+        assert_eq!(error.exit_code(), 2);
+        let msg = error.to_string();
+        assert!(
+            msg.contains("secrets_tables/conf/../secrets.toml"),
+            "{}",
+            msg
+        );
+    }
+
+    #[test]
+    fn clock_skew_is_how_far_ahead_the_system_is() {
+        let system = utc(2026, 10, 14, 12, 0);
+        let server = |minutes: i64| (system - chrono::Duration::minutes(minutes)).timestamp();
+        assert_eq!(
+            clock_skew(system, system.timestamp()),
+            chrono::Duration::zero()
+        );
+        assert_eq!(clock_skew(system, server(20)).num_minutes(), 20);
+        assert_eq!(clock_skew(system, server(-20)).num_minutes(), -20);
+
+        // num_minutes truncates, so it takes a whole minute past the warning
+        let just_over = server(CLOCK_SKEW_WARNING_MINUTES) - 59;
+        assert_eq!(
+            clock_skew(system, just_over).num_minutes(),
+            CLOCK_SKEW_WARNING_MINUTES
+        );
+    }
+
+    // shaped like a forecast_days=4 response for Ottawa, starting on a day
+    // already over there
+    const OPEN_METEO_RESPONSE: &str = r#"{
+        "latitude": 45.42, "longitude": -75.69, "utc_offset_seconds": -14400,
+        "timezone": "America/Toronto",
+        "current": {"time": "2026-10-14T08:00", "temperature_2m": 7.4,
+            "apparent_temperature": 4.9, "relative_humidity_2m": 83,
+            "pressure_msl": 1016.2, "uv_index": 0.8},
+        "daily": {
+            "time": ["2026-10-13", "2026-10-14", "2026-10-15", "2026-10-16"],
+            "weather_code": [3, 61, 0, 95],
+            "temperature_2m_max": [11.2, 10.5, 13.8, 15.1],
+            "temperature_2m_min": [3.1, 4.0, 2.6, 8.9],
+            "precipitation_probability_max": [10, 85, 0, null],
+            "wind_speed_10m_max": [12.0, 21.6, 8.3, 14.0],
+            "wind_direction_10m_dominant": [270, 225, 315, 180],
+            "sunrise": ["2026-10-13T07:11", "2026-10-14T07:12", "2026-10-15T07:14", "2026-10-16T07:15"],
+            "sunset": ["2026-10-13T18:21", "2026-10-14T18:20", "2026-10-15T18:18", "2026-10-16T18:16"]
+        }
+    }"#;
+
+    #[test]
+    fn open_meteo_response_is_read_for_the_locations_today() {
+        let config = test_config("weather_provider = \"open_meteo\"");
+        let agent = http_agent(&config);
+        // 08:00 on the 14th at UTC-4
+        let now = utc(2026, 10, 14, 12, 0).with_timezone(&Local);
+        let open_meteo = OpenMeteo::new(&config, &agent, now);
+        open_meteo
+            .response
+            .set(serde_json::from_str(OPEN_METEO_RESPONSE).unwrap())
+            .unwrap();
+
+        let outdoor = open_meteo.outdoor().unwrap();
+        assert_eq!(outdoor.temp, Some(4.9));
+        assert_eq!(outdoor.humidity, Some(83.0));
+        assert_eq!(outdoor.pressure, Some(1016.2));
+        assert_eq!(outdoor.uvi, Some(0.8));
+        assert_eq!(outdoor.aqi, None);
+        // the 14th's, in the location's time
+        let there = FixedOffset::west(4 * 3600);
+        assert_eq!(
+            outdoor.sunrise,
+            Some(there.ymd(2026, 10, 14).and_hms(7, 12, 0))
+        );
+        assert_eq!(
+            outdoor.sunset,
+            Some(there.ymd(2026, 10, 14).and_hms(18, 20, 0))
+        );
+
+        let forecast = open_meteo.forecast().unwrap();
+        assert_eq!(forecast.moon_phase, None);
+        let days: Vec<_> = forecast
+            .days
+            .iter()
+            .map(|day| {
+                (
+                    day.date.unwrap().day(),
+                    day.high,
+                    day.low,
+                    day.condition.as_str(),
+                    day.pop,
+                )
+            })
+            .collect();
+        assert_eq!(
+            days,
+            [
+                (14, 10.5, 4.0, "Rain", 0.85),
+                (15, 13.8, 2.6, "Clear", 0.0),
+                // a null chance of rain is none
+                (16, 15.1, 8.9, "Thunderstorm", 0.0),
+            ]
+        );
+        assert_eq!(forecast.days[0].condition_id, Some(501));
+        assert_eq!(forecast.days[0].wind_deg, 225.0);
+    }
+
+    #[test]
+    fn uvi_band_edges() {
+        assert_eq!(uvi_band(0.0), "Low");
+        assert_eq!(uvi_band(2.0), "Low");
+        // banded as it's drawn, rounded
+        assert_eq!(uvi_band(2.49), "Low");
+        assert_eq!(uvi_band(2.5), "Moderate");
+        assert_eq!(uvi_band(5.0), "Moderate");
+        assert_eq!(uvi_band(6.0), "High");
+        assert_eq!(uvi_band(7.0), "High");
+        assert_eq!(uvi_band(8.0), "Very High");
+        assert_eq!(uvi_band(10.0), "Very High");
+        assert_eq!(uvi_band(11.0), "Extreme");
+        assert_eq!(uvi_band(14.2), "Extreme");
+    }
+
+    #[test]
+    fn show_uvi_adds_the_index_and_its_band() {
+        assert_eq!(uvi_text(0.2), "UV 0 Low");
+        assert_eq!(uvi_text(2.5), "UV 3 Moderate");
+        assert_eq!(uvi_text(10.6), "UV 11 Extreme");
+
+        let now = Local.ymd(2026, 10, 14).and_hms(12, 0, 0);
+        let mut data = test_data(now);
+        let off = render(&test_config(""), &data, now).unwrap();
+        let on = render(&test_config("show_uvi = true"), &data, now).unwrap();
+        assert!(off != on);
+        // nothing to add without a reading
+        data.outdoor.as_mut().unwrap().uvi = None;
+        let on = render(&test_config("show_uvi = true"), &data, now).unwrap();
+        assert!(off == on);
+    }
+
+    #[test]
+    fn a_creeping_reading_is_compared_with_what_was_drawn() {
+        let config = test_config("[change_epsilon]\ntemp = 0.25");
+        let now = Local.ymd(2026, 10, 14).and_hms(12, 0, 0);
+        let path = temp_dir("creeping").join("last_drawn.json");
+        let at = |temp: f64| {
+            // the sparkline's newest point is the mean so far, so it creeps too
+            let mut data = test_data(now);
+            data.indoor[0].temp = Some(temp);
+            *data.indoor_history.last_mut().unwrap() = temp;
+            LastDrawn::new(&config, data, now)
+        };
+        save_last_drawn(&path, &at(20.0)).unwrap();
+
+        // as run does, only saving what's been drawn
+        let mut refreshed = Vec::new();
+        for step in 1..=6 {
+            let current = at(20.0 + 0.125 * step as f64);
+            let last = load_last_drawn(&path).unwrap();
+            if changed(&last, &current, &config.change_epsilon) {
+                refreshed.push(step);
+                save_last_drawn(&path, &current).unwrap();
+            }
+        }
+        // each step is under the epsilon, and landing right on it isn't over
+        assert_eq!(refreshed, [3, 6]);
+    }
+
+    #[test]
+    fn temp_delta_has_a_sign() {
+        let delta = |indoor, outdoor| {
+            format_reading(temp_delta(Some(indoor), Some(outdoor)), |d| {
+                Units::Metric.format_temp_delta(d, 1)
+            })
+        };
+        assert_eq!(delta(21.2, 6.0), "+15.2C");
+        assert_eq!(delta(18.0, 21.5), "-3.5C");
+        assert_eq!(delta(20.0, 20.0), "+0.0C");
+        assert_eq!(delta(20.0, 20.04), "+0.0C");
+        assert_eq!(Units::Imperial.format_temp_delta(-0.4, 0), "+0F");
+    }
+
+    #[test]
+    fn temp_delta_needs_both_temps() {
+        assert_eq!(temp_delta(None, Some(5.0)), None);
+        assert_eq!(temp_delta(Some(5.0), None), None);
+        assert_eq!(format_reading(None, |d| d.to_string()), PLACEHOLDER);
+    }
+
+    const CABIN: &str = r#"
+        [[locations]]
+        name = "Cabin"
+        lat = 46.5
+        lon = -78.1
+        days = ["sat", "sun"]
+
+        [[locations]]
+        name = "Office"
+        lat = 43.65
+        lon = -79.38
+        days = ["mon", "sun"]
+    "#;
+
+    #[test]
+    fn location_is_home_without_locations() {
+        let config = test_config("location_name = \"Home\"");
+        for weekday in [Weekday::Mon, Weekday::Sat] {
+            let location = config.location(weekday);
+            assert_eq!(location.name.as_deref(), Some("Home"));
+            assert_eq!((location.lat, location.lon), (45.42, -75.69));
+        }
+    }
+
+    #[test]
+    fn location_matches_its_days() {
+        let config = test_config(CABIN);
+        let name = |weekday| config.location(weekday).name;
+        assert_eq!(name(Weekday::Sat).as_deref(), Some("Cabin"));
+        assert_eq!(name(Weekday::Mon).as_deref(), Some("Office"));
+        // the first one listed wins when two share a day
+        assert_eq!(name(Weekday::Sun).as_deref(), Some("Cabin"));
+        // lat/lon on the days none are for
+        assert_eq!(config.location(Weekday::Wed).lat, 45.42);
+    }
+
+    #[test]
+    fn weather_sources_pick_the_location_for_the_run() {
+        let config = test_config(CABIN);
+        let agent = http_agent(&config);
+        // a saturday, whatever day the test runs on
+        let saturday = Local.ymd(2026, 10, 17).and_hms(23, 59, 0);
+        assert_eq!(
+            OpenWeather::new(&config, &agent, saturday).location.lat,
+            46.5
+        );
+        let monday = Local.ymd(2026, 10, 19).and_hms(0, 0, 0);
+        assert_eq!(OpenMeteo::new(&config, &agent, monday).location.lat, 43.65);
+    }
+
+    #[test]
+    fn cached_weather_is_only_for_its_location() {
+        let path = temp_dir("cached_weather_location").join("weather.json");
+        let config = test_config(CABIN);
+        let now = utc(2026, 10, 14, 12, 0);
+        save_cached_weather(&path, &config.home(), &serde_json::json!({}), now).unwrap();
+        let max_age = chrono::Duration::minutes(30);
+        let cabin = config.location(Weekday::Sat);
+        assert!(load_cached_weather(&path, &cabin, max_age, now).is_none());
+        assert!(load_cached_weather(&path, &config.home(), max_age, now).is_some());
+    }
+
+    #[test]
+    fn influxql_empty_series_is_no_row() {
+        // what a measurement or tag with nothing in the window gives back
+        let body = r#"{"results":[{"statement_id":0}]}"#;
+        assert!(InfluxQl.parse_latest(body).unwrap().is_none());
+        assert!(InfluxQl.parse_history(body).unwrap().is_empty());
+        let body = r#"{"results":[{"statement_id":0,"series":[{"name":"indoor",
+            "columns":["time","temperature"],"values":[]}]}]}"#;
+        assert!(InfluxQl.parse_latest(body).unwrap().is_none());
+    }
+
+    #[test]
+    fn influxql_error_body_is_an_error() {
+        let body = r#"{"results":[{"statement_id":0,"error":"database not found: home"}]}"#;
+        let result = InfluxQl::result(body);
+        assert!(
+            matches!(&result, Err(Oops::Data(msg)) if msg.contains("database not found: home"))
+        );
+        assert!(InfluxQl.parse_history(body).is_err());
+        // a bad query string fails the whole request rather than a result
+        let body = r#"{"error":"error parsing query: found EOF"}"#;
+        assert!(InfluxQl.parse_latest(body).is_err());
+        assert!(InfluxQl::result("not json").is_err());
+    }
+
+    #[test]
+    fn influx_error_object_propagates_its_message() {
+        let body =
+            r#"{"results":[{"statement_id":0,"error":"retention policy not found: autogen"}]}"#;
+        let (url, server) = fake_server(vec![(200, body.to_string())]);
+        let mut config = test_config("retries = 0");
+        config.influx_server = format!("{}/query", url);
+        let agent = influx_agent(&config).unwrap();
+        let error = InfluxDb::new(&config, &agent).indoor().err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "InfluxDB query failed: retention policy not found: autogen"
+        );
+        assert_eq!(error.exit_code(), 3);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn quiet_mode_picks_what_quiet_hours_show() {
+        let now = Local.ymd(2026, 10, 14).and_hms(23, 15, 0);
+        let text = |mode: &str, shown| {
+            let config = test_config(&format!("quiet_mode = \"{}\"", mode));
+            quiet_text(&config, now, shown)
+        };
+        assert_eq!(text("skip", None), None);
+        assert_eq!(text("clear", None).as_deref(), Some(""));
+        assert_eq!(text("clock", None).as_deref(), Some("23:15"));
+
+        // once a night, or once a minute for the clock
+        assert_eq!(text("clear", Some("")), None);
+        assert_eq!(text("clock", Some("23:15")), None);
+        assert_eq!(text("clock", Some("23:14")).as_deref(), Some("23:15"));
+        // the clock going to a cleared panel, or the other way round
+        assert_eq!(text("clear", Some("23:15")).as_deref(), Some(""));
+        assert_eq!(text("clock", Some("")).as_deref(), Some("23:15"));
+    }
+
+    #[test]
+    fn pressure_unit_auto_cuts_over_at_2000() {
+        assert_eq!(PressureUnit::Auto.to_hpa(1013.25), 1013.25);
+        assert_eq!(PressureUnit::Auto.to_hpa(2000.0), 2000.0);
+        assert_eq!(PressureUnit::Auto.to_hpa(2000.5), 20.005);
+        assert_eq!(PressureUnit::Auto.to_hpa(101325.0), 1013.25);
+    }
+
+    #[test]
+    fn pressure_units_given_are_followed() {
+        assert_eq!(PressureUnit::Hpa.to_hpa(101325.0), 101325.0);
+        assert_eq!(PressureUnit::Pa.to_hpa(1013.0), 10.13);
+        assert_eq!(PressureUnit::Pa.to_hpa(101325.0), 1013.25);
+    }
+
+    #[test]
+    fn indoor_pressure_in_pa_or_hpa_comes_out_in_hpa() {
+        let pressure = |conf: &str, reading: f64| {
+            let config = test_config(conf);
+            let row = test_row(21.0, 40.0, reading).calibrated(&config);
+            indoor_data("indoor", row, config.units).pressure
+        };
+        // auto takes anything over 2000 as Pa, no sea level pressure is near
+        assert_eq!(pressure("", 1013.25), Some(1013.25));
+        assert_eq!(pressure("", 101325.0), Some(1013.25));
+        assert_eq!(
+            pressure("indoor_pressure_unit = \"hpa\"", 1013.25),
+            Some(1013.25)
+        );
+        assert_eq!(
+            pressure("indoor_pressure_unit = \"pa\"", 101325.0),
+            Some(1013.25)
+        );
+        // the offset's in hPa whichever the sensor sends
+        let conf = "indoor_pressure_unit = \"pa\"\nindoor_pressure_offset = 1.5";
+        assert_eq!(pressure(conf, 101325.0), Some(1014.75));
+        // and then it's converted for imperial like the rest
+        let inhg = pressure("units = \"imperial\"", 101325.0).unwrap();
+        assert!((inhg - 29.92).abs() < 0.01, "{}", inhg);
+    }
+
+    #[test]
+    fn get_f64_fails_on_missing_null_and_non_numeric() {
+        let response = serde_json::json!({
+            "current": {"temp": 12.5, "humidity": null, "pressure": "1013"},
+            "daily": [{"temp": {"max": 14.0}}],
+        });
+        let error = |path: &[&str]| get_f64(&response, path).unwrap_err().to_string();
+        assert_eq!(get_f64(&response, &["current", "temp"]).unwrap(), 12.5);
+        assert_eq!(
+            get_f64(&response, &["daily", "0", "temp", "max"]).unwrap(),
+            14.0
+        );
+        assert!(error(&["current", "uvi"]).contains("current.uvi missing"));
+        assert!(error(&["current", "humidity"])
+            .contains("current.humidity in the response isn't a number"));
+        assert!(error(&["current", "pressure"])
+            .contains("current.pressure in the response isn't a number"));
+        assert!(error(&["daily", "1", "temp"]).contains("daily.1 missing"));
+    }
+
+    #[test]
+    fn openweather_forecast_names_a_missing_high() {
+        let now = Local.ymd(2026, 10, 14).and_hms(12, 0, 0);
+        let config = test_config("");
+        let agent = http_agent(&config);
+        let forecast = |change: &dyn Fn(&mut serde_json::Value)| {
+            let mut response: serde_json::Value = serde_json::from_str(&onecall(now)).unwrap();
+            change(&mut response);
+            let openweather = OpenWeather::new(&config, &agent, now);
+            openweather.response.set(response).unwrap();
+            openweather.forecast()
+        };
+        assert!(forecast(&|_| {}).is_ok());
+
+        let error = forecast(&|r| {
+            r["daily"][0]["temp"].as_object_mut().unwrap().remove("max");
+        })
+        .err()
+        .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "daily.0.temp.max missing from the response"
+        );
+        assert_eq!(error.exit_code(), 3);
+
+        let error = forecast(&|r| r["daily"][1]["temp"]["min"] = "cold".into())
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "daily.1.temp.min in the response isn't a number"
+        );
+    }
+
+    #[cfg(feature = "mqtt")]
+    #[test]
+    fn mqtt_remaining_length_is_a_varint() {
+        for (len, encoded) in [
+            (0, vec![0x00]),
+            (127, vec![0x7f]),
+            (128, vec![0x80, 0x01]),
+            (321, vec![0xc1, 0x02]),
+            (16_384, vec![0x80, 0x80, 0x01]),
+        ] {
+            let mut packet = Vec::new();
+            write_mqtt_packet(&mut packet, 0x30, &vec![7; len]).unwrap();
+            assert_eq!(packet[0], 0x30);
+            assert_eq!(packet[1..1 + encoded.len()], encoded[..], "{} bytes", len);
+            let (header, body) = read_mqtt_packet(&mut packet.as_slice()).unwrap();
+            assert_eq!((header, body.len()), (0x30, len));
+        }
+
+        // the biggest four bytes can say, far over MQTT_MAX_PACKET
+        let huge = [0x30, 0xff, 0xff, 0xff, 0x7f];
+        let err = read_mqtt_packet(&mut huge.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    // a fixed header and what comes after the remaining length
+    #[cfg(feature = "mqtt")]
+    type Packet = (u8, Vec<u8>);
+
+    // Answers one client's CONNECT, then its SUBSCRIBE with `replies`, and
+    // hands back every packet it was sent
+    #[cfg(feature = "mqtt")]
+    fn fake_broker(replies: Vec<Packet>) -> (u16, thread::JoinHandle<Vec<Packet>>) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let broker = thread::spawn(move || {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            let mut stream = loop {
+                match listener.accept() {
+                    Ok((stream, _)) => break stream,
+                    Err(_) if Instant::now() < deadline => thread::sleep(Duration::from_millis(5)),
+                    Err(_) => return Vec::new(),
+                }
+            };
+            stream.set_nonblocking(false).unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            let mut received = vec![read_mqtt_packet(&mut stream).unwrap()];
+            write_mqtt_packet(&mut stream, 0x20, &[0, 0]).unwrap();
+            received.push(read_mqtt_packet(&mut stream).unwrap());
+            for (header, body) in replies {
+                write_mqtt_packet(&mut stream, header, &body).unwrap();
+            }
+            // then whatever's left until the client hangs up
+            while let Ok(packet) = read_mqtt_packet(&mut stream) {
+                received.push(packet);
+            }
+            received
+        });
+        (port, broker)
+    }
+
+    #[cfg(feature = "mqtt")]
+    fn publish(topic: &str, payload: &str) -> Vec<u8> {
+        let mut body = mqtt_string(topic);
+        body.extend(payload.as_bytes());
+        body
+    }
+
+    #[cfg(feature = "mqtt")]
+    #[test]
+    fn mqtt_missing_retained_topic_is_none() {
+        // a retained PUBLISH before the SUBACK, one at QoS 1 with its packet
+        // id after, and nothing at all on humidity
+        let mut qos1 = publish("home/pressure", "1012.5");
+        qos1.splice(15..15, [0, 9]);
+        let (port, broker) = fake_broker(vec![
+            (0x31, publish("home/temp", "21.25")),
+            (0x90, vec![0, 1, 0, 0, 0]),
+            (0x33, qos1),
+        ]);
+        let config = test_config(&format!(
+            "indoor_source = \"mqtt\"\ntimeout_read_secs = 10\n[mqtt]\nhost = \"127.0.0.1\"\n\
+             port = {}\nclient_id = \"malter\"\ntemperature_topic = \"home/temp\"\n\
+             humidity_topic = \"home/humidity\"\npressure_topic = \"home/pressure\"",
+            port
+        ));
+
+        let started = Instant::now();
+        let indoor = Mqtt::new(&config).indoor().unwrap();
+        // MQTT_RETAINED_WAIT, not the 10s read timeout
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(indoor[0].temp, Some(21.25));
+        assert_eq!(indoor[0].humidity, None);
+        assert_eq!(indoor[0].pressure, Some(1012.5));
+
+        let received = broker.join().unwrap();
+        // CONNECT: "MQTT" level 4, a clean session, 60s keep alive, the id
+        let connect = b"\x00\x04MQTT\x04\x02\x00\x3c\x00\x06malter";
+        assert_eq!(received[0], (0x10, connect.to_vec()));
+        // SUBSCRIBE: packet id 1 and each topic at QoS 0
+        let subscribe = b"\x00\x01\x00\x09home/temp\x00\x00\x0dhome/humidity\x00\
+                          \x00\x0dhome/pressure\x00";
+        assert_eq!(received[1], (0x82, subscribe.to_vec()));
+        assert_eq!(received[2], (0xe0, Vec::new()));
+    }
+
+    #[test]
+    fn snapshot_two_column() {
+        let (data, now) = snapshot_data();
+        let config = test_config("layout = \"two_column\"");
+        assert_snapshot("two_column", &config, &data, now);
+        let config = test_config("layout = \"two_column\"\npanel = \"7in5\"");
+        assert_snapshot("two_column_7in5", &config, &data, now);
+    }
+
+    #[test]
+    fn busy_pin_gives_up_after_the_timeout() {
+        let timed_out = Rc::new(Cell::new(false));
+        let busy = BusyPin {
+            pin: Pin::new(24),
+            timeout: Duration::from_millis(20),
+            busy_since: Cell::new(None),
+            timed_out: Rc::clone(&timed_out),
+        };
+        assert!(busy.poll(true).unwrap());
+        // going idle starts the next wait over
+        assert!(!busy.poll(false).unwrap());
+        assert_eq!(busy.busy_since.get(), None);
+        assert!(busy.poll(true).unwrap());
+        thread::sleep(Duration::from_millis(30));
+        assert!(busy.poll(true).is_err());
+        assert!(timed_out.get());
+        // and every read after fails at once, idle or not
+        assert!(busy.poll(false).is_err());
+    }
+
+    struct NoRooms;
+
+    impl IndoorSource for NoRooms {
+        fn indoor(&self) -> Result<Vec<IndoorData>> {
+            Err(Oops::Data("no rooms".to_string()))
+        }
+    }
+
+    #[test]
+    fn run_summary_notes_each_fetch() {
+        let sources = RefCell::new(Vec::new());
+        let indoor: &dyn IndoorSource = &NoRooms;
+        assert!(Timed::new(indoor, &sources).indoor().is_err());
+        let mut summary = RunSummary::new(Local.ymd(2026, 10, 14).and_hms(12, 0, 0));
+        summary.sources = sources.into_inner();
+
+        let json = serde_json::to_value(&summary).unwrap();
+        assert_eq!(json["outcome"], "failed");
+        assert_eq!(json["quiet"], false);
+        // left out rather than null when there's none
+        assert!(json.get("error").is_none());
+        let source = &json["sources"][0];
+        assert_eq!(source["name"], "indoor");
+        assert_eq!(source["ok"], false);
+        assert_eq!(source["error"], "no rooms");
+        assert!(source["millis"].is_u64());
+        assert_eq!(
+            serde_json::to_value(Outcome::DryRun).unwrap(),
+            serde_json::json!("dry_run")
+        );
+    }
+
+    // hands out test_data, counting how often the forecast's asked for
+    #[derive(Default)]
+    struct Canned {
+        forecasts: Cell<usize>,
+    }
+
+    impl IndoorSource for Canned {
+        fn indoor(&self) -> Result<Vec<IndoorData>> {
+            Ok(test_data(Local::now()).indoor)
+        }
+    }
+
+    impl WeatherSource for Canned {
+        fn outdoor(&self) -> Result<OutdoorData> {
+            Err(Oops::Data("no outdoor today".to_string()))
+        }
+
+        fn forecast(&self) -> Result<ForecastData> {
+            self.forecasts.set(self.forecasts.get() + 1);
+            Ok(test_data(Local::now()).forecast.unwrap())
+        }
+    }
+
+    #[test]
+    fn show_forecast_off_skips_the_fetch_and_its_column() {
+        let source = Canned::default();
+        let data = get_data(&source, &source, true).unwrap();
+        assert_eq!(source.forecasts.get(), 1);
+        assert_eq!(data.forecast.unwrap().days.len(), 3);
+
+        // only the outdoor failed, so that's still Ok
+        let data = get_data(&source, &source, false).unwrap();
+        assert_eq!(source.forecasts.get(), 1);
+        assert!(data.forecast.is_none());
+        assert_eq!(data.indoor.len(), 1);
+
+        // even with a forecast to hand it's drawn two_column
+        let (data, now) = snapshot_data();
+        let config = test_config("show_forecast = false");
+        assert_snapshot("two_column", &config, &data, now);
+    }
+
+    #[test]
+    fn load_hardware_keeps_only_the_wiring() {
+        let dir = temp_dir("load_hardware");
+        let path = dir.join("conf.toml");
+        // nothing selftest doesn't read is checked, not even that it's a key
+        fs::write(
+            &path,
+            "panel = \"7in5\"\nretries = \"three\"\nnot_a_key = 1\n[pins]\ncs = 7\nbusy = 5\ndc = 6\nrst = 4\n",
+        )
+        .unwrap();
+        let config = Config::load_hardware(path.to_str().unwrap()).unwrap();
+        assert!(config.panel == Panel::Epd7in5);
+        assert_eq!((config.pins.cs, config.pins.busy), (7, 5));
+
+        // and without a config it's the default wiring
+        let config = Config::load_hardware(dir.join("missing.toml").to_str().unwrap()).unwrap();
+        assert!(config.panel == Panel::Epd2in9);
+        assert_eq!(config.pins.cs, Pins::default().cs);
+    }
+
+    // hourly entries from `start` on, one an hour with the given temps
+    fn hourly(start: DateTime<Utc>, temps: &[f64]) -> Vec<serde_json::Value> {
+        temps
+            .iter()
+            .enumerate()
+            .map(|(i, temp)| serde_json::json!({"dt": start.timestamp() + i as i64 * 3600, "temp": temp}))
+            .collect()
+    }
+
+    #[test]
+    fn remaining_day_range_stops_at_local_midnight() {
+        // 21:00 to 02:00 at UTC-4, hours after midnight there are tomorrow's
+        let offset = -4 * 3600;
+        let hours = hourly(utc(2026, 10, 15, 1, 0), &[9.0, 8.0, 7.5, 3.0, 2.0, 1.0]);
+        let now = utc(2026, 10, 15, 1, 30).timestamp();
+        assert_eq!(remaining_day_range(&hours, offset, now), Some((7.5, 9.0)));
+        // past midnight there it's the new day's hours
+        let now = utc(2026, 10, 15, 4, 10).timestamp();
+        assert_eq!(remaining_day_range(&hours, offset, now), Some((1.0, 3.0)));
+        // in UTC the same hours are all one day
+        let now = utc(2026, 10, 15, 1, 30).timestamp();
+        assert_eq!(remaining_day_range(&hours, 0, now), Some((1.0, 9.0)));
+    }
+
+    #[test]
+    fn remaining_day_high_low_uses_the_hours_left() {
+        // midday at the location, whatever zone the tests run in
+        let now = utc(2026, 10, 14, 16, 0).with_timezone(&Local);
+        let config = test_config("remaining_day_high_low = true");
+        let agent = http_agent(&config);
+        let forecast = |hourly: Option<Vec<serde_json::Value>>| {
+            let mut response: serde_json::Value = serde_json::from_str(&onecall(now)).unwrap();
+            if let Some(hourly) = hourly {
+                response["hourly"] = hourly.into();
+            }
+            let openweather = OpenWeather::new(&config, &agent, now);
+            openweather.response.set(response).unwrap();
+            let days = openweather.forecast().unwrap().days;
+            days.iter()
+                .map(|day| (day.low, day.high))
+                .collect::<Vec<_>>()
+        };
+
+        // the two hours already over don't count, the one it's in does
+        let start = now.with_timezone(&Utc) - chrono::Duration::hours(2);
+        let hours = hourly(start, &[30.0, 25.0, 9.0, 11.0, 13.5, 10.0]);
+        assert_eq!(
+            forecast(Some(hours)),
+            [(9.0, 13.5), (2.0, 15.5), (2.0, 16.5)]
+        );
+
+        // without hourly, or none of it left, it's the whole day's
+        let whole_day = [(2.0, 14.5), (2.0, 15.5), (2.0, 16.5)];
+        assert_eq!(forecast(None), whole_day);
+        assert_eq!(forecast(Some(Vec::new())), whole_day);
+        assert_eq!(forecast(Some(hourly(start, &[30.0, 25.0]))), whole_day);
+    }
+
+    #[test]
+    fn wrapped_lines_breaks_like_a_text_box() {
+        assert_eq!(wrapped_lines("hello world", 20), 1);
+        // filling the line exactly still fits
+        assert_eq!(wrapped_lines("hello world", 11), 1);
+        assert_eq!(wrapped_lines("hello world", 10), 2);
+        // a word too long for a line is broken across them
+        assert_eq!(wrapped_lines("abcdefghij", 4), 3);
+        assert_eq!(wrapped_lines("one\ntwo three", 5), 3);
+    }
+
+    #[test]
+    fn color_accents_go_on_their_own_layer() {
+        let now = Local.ymd(2026, 10, 14).and_hms(12, 0, 0);
+        let mut data = test_data(now);
+        let conf = "panel = \"2in9bc\"\ncolor_accents = true\nrain_banner_threshold = 0.6";
+        let config = test_config(conf);
+        let blank = blank_buffer(config.panel);
+        data.forecast.as_mut().unwrap().days[0].pop = 0.0;
+        assert_eq!(
+            render_accents(&config, &data, now).unwrap(),
+            Some(blank.clone())
+        );
+        data.forecast.as_mut().unwrap().days[0].pop = 0.9;
+        let accents = render_accents(&config, &data, now).unwrap().unwrap();
+        assert!(accents != blank);
+        // and the black frame's the same either way
+        let without = test_config("panel = \"2in9bc\"\nrain_banner_threshold = 0.6");
+        assert!(render(&config, &data, now).unwrap() == render(&without, &data, now).unwrap());
+        assert_eq!(render_accents(&without, &data, now).unwrap(), None);
+
+        // there's nowhere for them to go on a black and white panel
+        let error = load_conf(
+            "accents_on_2in9",
+            &format!("{}color_accents = true\n", MINIMAL_CONF),
+            Vec::new(),
+        )
+        .err()
+        .unwrap();
+        assert_eq!(error.exit_code(), 2);
+        assert!(error.to_string().contains("color_accents"), "{}", error);
+    }
+
+    // what went over the fake bus, a command's the byte sent with DC low
+    #[derive(Debug, PartialEq)]
+    enum Wire {
+        Reset,
+        Command(u8),
+        Data(usize),
+    }
+
+    // stands in for the spidev and every pin, sharing the one log
+    #[derive(Clone)]
+    struct FakeWire {
+        pin: &'static str,
+        log: Rc<RefCell<Vec<Wire>>>,
+        dc_high: Rc<Cell<bool>>,
+    }
+
+    impl FakeWire {
+        fn as_pin(&self, pin: &'static str) -> FakeWire {
+            FakeWire {
+                pin,
+                ..self.clone()
+            }
+        }
+
+        fn commands(&self) -> Vec<u8> {
+            let log = self.log.borrow();
+            let commands = log.iter().filter_map(|wire| match wire {
+                Wire::Command(command) => Some(*command),
+                _ => None,
+            });
+            commands.collect()
+        }
+    }
+
+    impl Write<u8> for FakeWire {
+        type Error = std::convert::Infallible;
+
+        fn write(&mut self, bytes: &[u8]) -> result::Result<(), Self::Error> {
+            let mut log = self.log.borrow_mut();
+            if !self.dc_high.get() {
+                log.extend(bytes.iter().map(|&byte| Wire::Command(byte)));
+            } else if let Some(Wire::Data(len)) = log.last_mut() {
+                *len += bytes.len();
+            } else {
+                log.push(Wire::Data(bytes.len()));
+            }
+            Ok(())
+        }
+    }
+
+    impl OutputPin for FakeWire {
+        type Error = std::convert::Infallible;
+
+        fn set_low(&mut self) -> result::Result<(), Self::Error> {
+            match self.pin {
+                "dc" => self.dc_high.set(false),
+                "rst" => self.log.borrow_mut().push(Wire::Reset),
+                _ => {}
+            }
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> result::Result<(), Self::Error> {
+            if self.pin == "dc" {
+                self.dc_high.set(true);
+            }
+            Ok(())
+        }
+    }
+
+    // never busy, the 2in9's BUSY is high while it is
+    impl InputPin for FakeWire {
+        type Error = std::convert::Infallible;
+
+        fn is_high(&self) -> result::Result<bool, Self::Error> {
+            Ok(false)
+        }
+
+        fn is_low(&self) -> result::Result<bool, Self::Error> {
+            Ok(true)
+        }
+    }
+
+    struct NoDelay;
+
+    impl DelayMs<u8> for NoDelay {
+        fn delay_ms(&mut self, _ms: u8) {}
+    }
+
+    fn fake_epd(
+        timed_out: Rc<Cell<bool>>,
+    ) -> (
+        AwakeEpd<FakeWire, FakeWire, FakeWire, FakeWire, FakeWire>,
+        FakeWire,
+    ) {
+        let wire = FakeWire {
+            pin: "spi",
+            log: Rc::default(),
+            dc_high: Rc::default(),
+        };
+        let mut spi = wire.clone();
+        let epd = Epd::new(
+            Panel::Epd2in9,
+            &mut spi,
+            wire.as_pin("cs"),
+            wire.as_pin("busy"),
+            wire.as_pin("dc"),
+            wire.as_pin("rst"),
+            &mut NoDelay,
+        )
+        .unwrap();
+        let epd = AwakeEpd::new(epd, spi, timed_out, Duration::from_secs(30)).unwrap();
+        (epd, wire)
+    }
+
+    #[test]
+    fn epd_inits_updates_then_sleeps() {
+        let (mut epd, wire) = fake_epd(Rc::default());
+        // reset, then driver output, booster, VCOM, dummy lines, gate time,
+        // data entry mode and the full refresh LUT
+        assert_eq!(wire.log.borrow()[0], Wire::Reset);
+        assert_eq!(wire.commands(), [0x01, 0x0c, 0x2c, 0x3a, 0x3b, 0x11, 0x32]);
+        assert_eq!(wire.log.borrow().last(), Some(&Wire::Data(30)));
+
+        wire.log.borrow_mut().clear();
+        epd.update_and_display_frame(&[0xff; 128 / 8 * 296])
+            .unwrap();
+        // the RAM window and counters, the frame, then update and activate
+        assert_eq!(
+            wire.commands(),
+            [0x44, 0x45, 0x4e, 0x4f, 0x24, 0x22, 0x20, 0xff]
+        );
+        assert!(wire.log.borrow().contains(&Wire::Data(128 / 8 * 296)));
+
+        wire.log.borrow_mut().clear();
+        epd.sleep().unwrap();
+        assert_eq!(*wire.log.borrow(), [Wire::Command(0x10), Wire::Data(1)]);
+    }
+
+    #[test]
+    fn epd_dropped_awake_is_put_to_sleep() {
+        let (mut epd, wire) = fake_epd(Rc::default());
+        epd.update_and_display_frame(&[0xff; 128 / 8 * 296])
+            .unwrap();
+        wire.log.borrow_mut().clear();
+        drop(epd);
+        assert_eq!(wire.commands(), [0x10]);
+
+        // and when a call fails part way, as with BUSY stuck
+        let timed_out = Rc::new(Cell::new(false));
+        let (mut epd, wire) = fake_epd(timed_out.clone());
+        timed_out.set(true);
+        let err = epd.update_and_display_frame(&[0xff; 128 / 8 * 296]);
+        assert!(matches!(err, Err(Oops::Display(_))));
+        wire.log.borrow_mut().clear();
+        drop(epd);
+        assert_eq!(wire.commands(), [0x10]);
+    }
+}
@@ -1,315 +1,364 @@
-use linux_embedded_hal::{
-    spidev::{self, SpidevOptions},
-    sysfs_gpio::Direction,
-    Delay, Pin, Spidev,
+use malter::{Config, DryRun, Oops, Outcome, Result, RunSummary};
+
+use std::{
+    any::Any,
+    env,
+    panic::{self, AssertUnwindSafe},
+    process,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant},
 };
 
-use embedded_graphics::{
-    fonts::{Font12x16, Font8x16},
-    pixelcolor::BinaryColor::On as Black,
-    prelude::*,
-    primitives::Rectangle,
-    style::PrimitiveStyleBuilder,
-};
-
-use epd_waveshare::{
-    epd2in9::*,
-    graphics::{Display, DisplayRotation},
-    prelude::*,
-};
-
-use embedded_text::{alignment::center::CenterAligned, prelude::*};
-
-use ureq;
-
-use std::{error, fmt, fs, result};
-
-use serde::Deserialize;
-
 use chrono::prelude::*;
 
-#[derive(Deserialize)]
-struct Config {
-    influx_server: String,
-    influx_database: String,
-    lat: String,
-    lon: String,
-    openweather_api_key: String,
-}
-
-struct IndoorData {
-    temp: f64,
-    humidity: f64,
-    pressure: f64,
+use log::{error, info, LevelFilter};
+
+// written in place of updating the EPD with --dry-run
+const DRY_RUN_PNG: &str = "malter.png";
+
+const HELP: &str = "\
+usage: malter [clear | selftest | message <text>] [conf.toml] [flags]
+
+Draws the indoor readings, outdoor weather and forecast on the EPD once, or
+with `clear` blanks it. `message` shows the text as big as it fits instead,
+until the next refresh puts the readings back. `selftest` shows a test
+pattern and how long the EPD took, for checking the wiring. It only reads
+the panel, rotation, pins and spi and busy keys, and works without a config
+at all. The config defaults to conf.toml, see conf-sample.toml for every
+key. Any top level key can also be set as MALTER_<KEY> in the environment.
+
+flags:
+  --dry-run   write the frame to malter.png instead of the EPD
+  --preview   print the frame to the terminal instead of the EPD
+  --daemon    keep refreshing every refresh_interval_minutes
+  --version   print the version and git hash
+  --help      print this
+
+exit codes: 0 ok, 1 io, 2 config, 3 fetching data, 4 the EPD
+";
+
+// what to do with the EPD, the first argument when it isn't a refresh
+#[derive(PartialEq)]
+enum Command {
+    Refresh,
+    Clear,
+    Selftest,
+    Message(String),
 }
 
-#[derive(Debug)]
-struct OutdoorData {
-    temp: f64,
-    humidity: f64,
-    pressure: f64,
+struct Args {
+    // --help and --version, which skip everything else
+    help: bool,
+    version: bool,
+    command: Command,
+    conf_path: String,
+    dry_run: Option<DryRun<'static>>,
+    daemon: bool,
 }
 
-struct ForecastData {
-    high: f64,
-    low: f64,
-    description: String,
-    pop: f64,
+fn parse_args() -> Result<Args> {
+    let mut args = env::args().skip(1).peekable();
+    // `malter clear [conf.toml]` blanks the panel instead of a refresh
+    let command = match args.peek().map(String::as_str) {
+        Some("clear") => Command::Clear,
+        Some("selftest") => Command::Selftest,
+        // the text's taken as it is, even if it looks like a flag
+        Some("message") => {
+            args.next();
+            let text = args
+                .peek()
+                .cloned()
+                .ok_or_else(|| Oops::Config("message needs the text to show".to_string()))?;
+            Command::Message(text)
+        }
+        _ => Command::Refresh,
+    };
+    if command != Command::Refresh {
+        args.next();
+    }
+    let mut conf_path = None;
+    let mut dry_run = None;
+    let mut daemon = false;
+    let (mut help, mut version) = (false, false);
+    for arg in args {
+        match arg.as_str() {
+            "--help" | "-h" => help = true,
+            "--version" | "-V" => version = true,
+            // --preview prints the frame, for a look over ssh
+            "--dry-run" | "--preview" if dry_run.is_some() => {
+                return Err(Oops::Config(
+                    "--dry-run and --preview can't be used together".to_string(),
+                ));
+            }
+            "--dry-run" => dry_run = Some(DryRun::Png(DRY_RUN_PNG)),
+            "--preview" => dry_run = Some(DryRun::Preview),
+            "--daemon" => daemon = true,
+            flag if flag.starts_with('-') => {
+                return Err(Oops::Config(format!("Unknown flag {}, see --help", flag)));
+            }
+            _ if conf_path.is_none() => conf_path = Some(arg),
+            _ => return Err(Oops::Config(format!("Unexpected argument {}", arg))),
+        }
+    }
+    if help || version {
+        return Ok(Args {
+            help,
+            version,
+            command,
+            conf_path: String::new(),
+            dry_run,
+            daemon,
+        });
+    }
+    let name = match command {
+        Command::Refresh => None,
+        Command::Clear => Some("clear"),
+        Command::Selftest => Some("selftest"),
+        Command::Message(_) => Some("message"),
+    };
+    if let Some(name) = name.filter(|_| dry_run.is_some() || daemon) {
+        return Err(Oops::Config(format!(
+            "{} can't be used with --dry-run, --preview or --daemon",
+            name
+        )));
+    }
+    Ok(Args {
+        help,
+        version,
+        command,
+        conf_path: conf_path.unwrap_or_else(|| "conf.toml".to_string()),
+        dry_run,
+        daemon,
+    })
 }
 
-#[derive(Debug)]
-struct Oops(String);
+// Logs to stderr for journald to pick up, RUST_LOG picks the level like
+// env_logger does (`debug`, or `malter=debug`), defaulting to info
+struct StderrLogger;
 
-impl From<std::io::Error> for Oops {
-    fn from(e: std::io::Error) -> Oops {
-        Oops(e.to_string())
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::max_level()
     }
-}
 
-impl From<ureq::Error> for Oops {
-    fn from(e: ureq::Error) -> Oops {
-        Oops(e.to_string())
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("[{}] {}", record.level(), record.args());
+        }
     }
+
+    fn flush(&self) {}
 }
 
-impl From<toml::de::Error> for Oops {
-    fn from(e: toml::de::Error) -> Oops {
-        Oops(e.to_string())
+static LOGGER: StderrLogger = StderrLogger;
+
+fn init_logging() {
+    let level = env::var("RUST_LOG")
+        .ok()
+        .and_then(|filter| parse_log_filter(&filter))
+        .unwrap_or(LevelFilter::Info);
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(level);
     }
 }
 
-impl error::Error for Oops {}
+// the last directive that applies to us wins, other crates' are ignored
+fn parse_log_filter(filter: &str) -> Option<LevelFilter> {
+    filter
+        .split(',')
+        .rev()
+        .find_map(|directive| match directive.split_once('=') {
+            Some((module, level)) if module.trim() == env!("CARGO_PKG_NAME") => {
+                level.trim().parse().ok()
+            }
+            Some(_) => None,
+            None => directive.trim().parse().ok(),
+        })
+}
 
-impl fmt::Display for Oops {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        self.0.fmt(f)
+// Exits with Oops::exit_code on failure, 0 otherwise, including runs skipped
+// for quiet hours
+fn main() {
+    init_logging();
+    if let Err(e) = try_main() {
+        error!("{}", e);
+        process::exit(e.exit_code());
     }
 }
 
-type Result<T> = result::Result<T, Oops>;
-
-fn main() -> Result<()> {
-    let local: DateTime<Local> = Local::now();
-    let hour = local.hour();
+fn try_main() -> Result<()> {
+    let args = parse_args()?;
 
-    if hour > 22 || hour < 7 {
-        println!("bed time, sleeping...");
+    // on stdout, for scripts checking what's deployed
+    if args.help {
+        print!("{}", HELP);
+        return Ok(());
+    }
+    if args.version {
+        println!(
+            "{} {} ({})",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION"),
+            env!("GIT_HASH")
+        );
         return Ok(());
     }
 
-    let conf_file =
-        fs::read_to_string("conf.toml").expect("Missing conf.toml, try copying conf-sample.toml");
-    let config: Config = toml::from_str(&conf_file)?;
-
-    let (mut epd, mut spi) = get_epd()?;
-
-    // Use display graphics from embedded-graphics
-    let mut display = Display2in9::default();
-    display.set_rotation(DisplayRotation::Rotate90);
-
-    let (indoor_data, outdoor_data, forecast_data) = get_data(&config)?;
-
-    draw(&mut display, &indoor_data, &outdoor_data, &forecast_data)?;
+    // before the config's loaded, so a broken one doesn't get in the way
+    if args.command == Command::Selftest {
+        let config = Config::load_hardware(&args.conf_path)?;
+        malter::selftest(&config)?;
+        info!("selftest done");
+        return Ok(());
+    }
 
-    // Display updated frame
-    epd.update_and_display_frame(&mut spi, &display.buffer())?;
+    let config = Config::load(&args.conf_path)?;
+    info!("loaded config from {}", args.conf_path);
+
+    // asked for by hand, so quiet hours don't apply
+    match &args.command {
+        Command::Clear => {
+            malter::clear(&config)?;
+            info!("display cleared");
+            return Ok(());
+        }
+        Command::Message(text) => {
+            malter::show_message(&config, text)?;
+            info!("message shown");
+            return Ok(());
+        }
+        Command::Refresh | Command::Selftest => {}
+    }
 
-    // Set the EPD to sleep
-    epd.sleep(&mut spi)?;
+    if !args.daemon {
+        return match run_once(&config, args.dry_run, &mut Vec::new()) {
+            Ok(result) => result,
+            Err(payload) => panic::resume_unwind(payload),
+        };
+    }
 
+    // every run leaves the EPD asleep, so once one is done there's nothing
+    // left to clean up before exiting
+    handle_terminate();
+    // drawn into again every refresh rather than allocated each time
+    let mut frame = Vec::new();
+    while !TERMINATED.load(Ordering::SeqCst) {
+        match run_once(&config, args.dry_run, &mut frame) {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => error!("refresh failed: {}", e),
+            Err(_) => error!("refresh panicked"),
+        }
+        sleep_until_terminated(config.refresh_interval());
+    }
+    info!("terminated, exiting");
     Ok(())
 }
 
-fn get_data(config: &Config) -> Result<(IndoorData, OutdoorData, ForecastData)> {
-    let response: serde_json::Value = ureq::get(&config.influx_server)
-        .query("pretty", "true")
-        .query("db", &config.influx_database)
-        .query("q", "SELECT MEAN(temperature) as temperature, MEAN(pressure) as pressure, MEAN(humidity) as humidity FROM \"indoor\" group by time(15m) order by time desc limit 1")
-        .call()?
-        .into_json()?;
-
-    let values = &response["results"][0]["series"][0]["values"][0];
+// One refresh, with a failure or panic shown on the display. A panic is handed
+// back for the caller to resume or shrug off.
+fn run_once(
+    config: &Config,
+    dry_run: Option<DryRun>,
+    frame: &mut Vec<u8>,
+) -> thread::Result<Result<()>> {
+    let local: DateTime<Local> = Local::now();
+    let mut summary = RunSummary::new(local);
+    let result = refresh(config, local, dry_run, frame, &mut summary);
+
+    // on stdout, apart from the log lines
+    if config.json_summary() {
+        match serde_json::to_string(&summary) {
+            Ok(json) => println!("{}", json),
+            Err(e) => error!("failed to write the run summary: {}", e),
+        }
+    }
+    result
+}
 
-    let indoor_data = IndoorData {
-        temp: values[1].as_f64().unwrap_or(0.0),
-        humidity: values[3].as_f64().unwrap_or(0.0),
-        pressure: values[2].as_f64().unwrap_or(0.0),
-    };
+fn refresh(
+    config: &Config,
+    local: DateTime<Local>,
+    dry_run: Option<DryRun>,
+    frame: &mut Vec<u8>,
+    summary: &mut RunSummary,
+) -> thread::Result<Result<()>> {
+    if dry_run.is_none() && config.is_quiet(local.hour()) {
+        info!("bed time, sleeping...");
+        summary.quiet = true;
+        // a failure here is only logged, there's nothing worth showing it on
+        let result = panic::catch_unwind(|| malter::run_quiet(config, local));
+        match &result {
+            Ok(Ok(())) => summary.outcome = Outcome::Quiet,
+            Ok(Err(e)) => summary.error = Some(e.to_string()),
+            Err(payload) => summary.error = Some(panic_message(payload.as_ref())),
+        }
+        return result;
+    }
 
-    let response: serde_json::Value = ureq::get("https://api.openweathermap.org/data/2.5/onecall")
-        .query("lat", &config.lat)
-        .query("lon", &config.lon)
-        .query("appid", &config.openweather_api_key)
-        .query("units", "metric")
-        .call()?
-        .into_json()?;
-
-    let outdoor_data = OutdoorData {
-        temp: response["current"]["feels_like"].as_f64().unwrap_or(0.0),
-        humidity: response["current"]["humidity"].as_f64().unwrap_or(0.0),
-        pressure: response["current"]["pressure"].as_f64().unwrap_or(0.0),
+    // a frame left half drawn by a panic is blanked before it's used again
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        malter::run_summarized(config, local, dry_run, frame, summary)
+    }));
+    let msg = match &result {
+        Ok(Ok(())) => return result,
+        Ok(Err(e)) => e.to_string(),
+        Err(payload) => panic_message(payload.as_ref()),
     };
+    summary.outcome = Outcome::Failed;
+    summary.error = Some(msg.clone());
+
+    // showing the error can fail or panic too, that shouldn't hide the
+    // original failure
+    if dry_run.is_none() {
+        match panic::catch_unwind(|| malter::show_error(config, &msg)) {
+            Ok(Ok(())) => info!("error shown on the display"),
+            Ok(Err(e)) => error!("failed to show the error on the display: {}", e),
+            Err(_) => error!("failed to show the error on the display"),
+        }
+    }
 
-    let forecast_data = ForecastData {
-        high: response["daily"][0]["temp"]["max"].as_f64().unwrap_or(0.0),
-        low: response["daily"][0]["temp"]["min"].as_f64().unwrap_or(0.0),
-        description: response["daily"][0]["weather"][0]["description"].to_string(),
-        pop: response["daily"][0]["pop"].as_f64().unwrap_or(0.0),
-    };
+    result
+}
 
+// Set by SIGTERM (or SIGINT) in daemon mode, checked between refreshes so one
+// that's under way finishes and leaves the EPD asleep
+static TERMINATED: AtomicBool = AtomicBool::new(false);
 
-    return Ok((indoor_data, outdoor_data, forecast_data));
+extern "C" fn on_terminate(_signal: libc::c_int) {
+    TERMINATED.store(true, Ordering::SeqCst);
 }
 
-fn draw(
-    display: &mut Display2in9,
-    indoor_data: &IndoorData,
-    outdoor_data: &OutdoorData,
-    forecast_data: &ForecastData,
-) -> Result<()> {
-    let big_text_style = TextBoxStyleBuilder::new(Font12x16)
-        .text_color(Black)
-        .alignment(CenterAligned)
-        .vertical_alignment(CenterAligned)
-        .build();
-
-    let small_text_style = TextBoxStyleBuilder::new(Font8x16)
-        .text_color(Black)
-        .alignment(CenterAligned)
-        .vertical_alignment(CenterAligned)
-        .build();
-
-    let line_style = PrimitiveStyleBuilder::new()
-        .stroke_color(Black)
-        .stroke_width(1)
-        .build();
-
-    // left column indoor data
-    let left_top = Rectangle::new(
-        Point::new(0, 0),
-        Point::new(HEIGHT as i32 / 3, WIDTH as i32 / 2),
-    );
-    let left_bottom = Rectangle::new(
-        Point::new(0, WIDTH as i32 / 2),
-        Point::new(HEIGHT as i32 / 3, WIDTH as i32),
-    );
-    let temp_txt = format!("{:.1}C", indoor_data.temp);
-    let text_box1 = TextBox::new(&temp_txt, left_top).into_styled(big_text_style);
-    text_box1.draw(display).expect("impossible");
-
-    left_top
-        .into_styled(line_style)
-        .draw(display)
-        .expect("impossible");
-
-    let minor_text = format!(
-        "{:.1}%\n{:.0} hPa",
-        indoor_data.humidity, indoor_data.pressure
-    );
-    let text_box2 = TextBox::new(&minor_text, left_bottom).into_styled(small_text_style);
-    text_box2.draw(display).expect("impossible");
-
-    left_bottom
-        .into_styled(line_style)
-        .draw(display)
-        .expect("impossible");
-
-    // middle outdoor temp
-    let middle_top = Rectangle::new(
-        Point::new(HEIGHT as i32 / 3, 0),
-        Point::new((HEIGHT as i32 / 3) * 2, WIDTH as i32 / 2),
-    );
-    let middle_bottom = Rectangle::new(
-        Point::new(HEIGHT as i32 / 3, WIDTH as i32 / 2),
-        Point::new((HEIGHT as i32 / 3) * 2, WIDTH as i32),
-    );
-
-    let temp_txt = format!("{:.1}C", outdoor_data.temp);
-    let text_box1 = TextBox::new(&temp_txt, middle_top).into_styled(big_text_style);
-    text_box1.draw(display).expect("impossible");
-
-    middle_top
-        .into_styled(line_style)
-        .draw(display)
-        .expect("impossible");
-
-    let minor_text = format!(
-        "{:.1}%\n{:.0} hPa",
-        outdoor_data.humidity, outdoor_data.pressure
-    );
-    let text_box2 = TextBox::new(&minor_text, middle_bottom).into_styled(small_text_style);
-    text_box2.draw(display).expect("impossible");
-
-    middle_bottom
-        .into_styled(line_style)
-        .draw(display)
-        .expect("impossible");
-
-    // right outdoor forecast
-    let right = Rectangle::new(
-        Point::new((HEIGHT as i32 / 3) * 2, 0),
-        Point::new(HEIGHT as i32, WIDTH as i32),
-    );
-
-    let forecast_text = format!(
-        "High: {:.1}\n  Low: {:.1}\n  Pop: {:.1}%\n\n{}",
-        forecast_data.high,
-        forecast_data.low,
-        forecast_data.pop,
-        forecast_data.description,
-    );
-
-    let text_box3 = TextBox::new(&forecast_text, right).into_styled(small_text_style);
-    text_box3.draw(display).expect("impossible");
-
-    right
-        .into_styled(line_style)
-        .draw(display)
-        .expect("impossible");
-
+fn handle_terminate() {
+    for signal in [libc::SIGTERM, libc::SIGINT] {
+        // only stores to an atomic, which is safe in a signal handler
+        unsafe {
+            libc::signal(
+                signal,
+                on_terminate as extern "C" fn(libc::c_int) as libc::sighandler_t,
+            );
+        }
+    }
+}
 
-    Ok(())
+// sleeps in short steps so a SIGTERM doesn't wait out the whole interval
+fn sleep_until_terminated(duration: Duration) {
+    let until = Instant::now() + duration;
+    while !TERMINATED.load(Ordering::SeqCst) {
+        let now = Instant::now();
+        if now >= until {
+            break;
+        }
+        thread::sleep((until - now).min(Duration::from_secs(1)));
+    }
 }
 
-fn get_epd() -> Result<(EPD2in9<Spidev, Pin, Pin, Pin, Pin>, Spidev)> {
-    // Configure SPI
-    // Settings are taken from
-    let mut spi = Spidev::open("/dev/spidev0.0").expect("spidev directory");
-    let options = SpidevOptions::new()
-        .bits_per_word(8)
-        .max_speed_hz(4_000_000)
-        .mode(spidev::SpiModeFlags::SPI_MODE_0)
-        .build();
-    spi.configure(&options).expect("spi configuration");
-
-    // Configure Digital I/O Pin to be used as Chip Select for SPI
-    let cs = Pin::new(24); //BCM7 CE0
-    cs.export().expect("cs export");
-    while !cs.is_exported() {}
-    cs.set_direction(Direction::Out).expect("CS Direction");
-    cs.set_value(1).expect("CS Value set to 1");
-
-    let busy = Pin::new(24); //pin 18
-    busy.export().expect("busy export");
-    while !busy.is_exported() {}
-    busy.set_direction(Direction::In).expect("busy Direction");
-    //busy.set_value(1).expect("busy Value set to 1");
-
-    let dc = Pin::new(25); //pin 22
-    dc.export().expect("dc export");
-    while !dc.is_exported() {}
-    dc.set_direction(Direction::Out).expect("dc Direction");
-    dc.set_value(1).expect("dc Value set to 1");
-
-    let rst = Pin::new(17); //pin 11
-    rst.export().expect("rst export");
-    while !rst.is_exported() {}
-    rst.set_direction(Direction::Out).expect("rst Direction");
-    rst.set_value(1).expect("rst Value set to 1");
-
-    let mut delay = Delay {};
-
-    // Setup EPD
-    let epd = EPD2in9::new(&mut spi, cs, busy, dc, rst, &mut delay)?;
-    return Ok((epd, spi));
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg.to_string()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "panicked".to_string()
+    }
 }
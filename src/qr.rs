@@ -0,0 +1,435 @@
+// Just enough of a QR code encoder for a link: byte mode at error correction
+// level L, versions 1 to 6. From 7 up there are version info blocks too, and
+// a link that long wouldn't scan off a panel this small anyway. Follows
+// ISO/IEC 18004, with the usual tricks from Project Nayuki's encoder.
+
+// (data codewords per block, blocks, ec codewords per block) at level L, by
+// version from 1
+const VERSIONS: [(usize, usize, usize); 6] = [
+    (19, 1, 7),
+    (34, 1, 10),
+    (55, 1, 15),
+    (80, 1, 20),
+    (108, 1, 26),
+    (68, 2, 18),
+];
+
+// The most bytes that fit, a version 6 code less the mode and length header
+pub const MAX_BYTES: usize = 134;
+
+pub struct QrCode {
+    size: usize,
+    dark: Vec<bool>,
+}
+
+impl QrCode {
+    // The smallest code holding data, None if it's over MAX_BYTES
+    pub fn encode(data: &[u8]) -> Option<QrCode> {
+        // 4 bits of mode and 8 of length round up to 2 codewords
+        let (version, &(block_len, blocks, ec_len)) = VERSIONS
+            .iter()
+            .enumerate()
+            .map(|(i, layout)| (i + 1, layout))
+            .find(|(_, (block_len, blocks, _))| data.len() + 2 <= block_len * blocks)?;
+        let codewords = codewords(data, block_len, blocks, ec_len);
+
+        let size = 17 + 4 * version;
+        let mut code = Matrix {
+            size,
+            dark: vec![false; size * size],
+            function: vec![false; size * size],
+        };
+        code.draw_function_patterns(version);
+        code.draw_codewords(&codewords);
+
+        // the mask that leaves the fewest patterns a reader could trip on
+        let mask = (0..8)
+            .min_by_key(|&mask| {
+                code.apply_mask(mask);
+                code.draw_format_bits(mask);
+                let penalty = code.penalty();
+                code.apply_mask(mask);
+                penalty
+            })
+            .unwrap_or(0);
+        code.apply_mask(mask);
+        code.draw_format_bits(mask);
+
+        Some(QrCode {
+            size,
+            dark: code.dark,
+        })
+    }
+
+    // modules along each side, without any quiet zone
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn is_dark(&self, x: usize, y: usize) -> bool {
+        self.dark[y * self.size + x]
+    }
+}
+
+// The byte mode segment padded out to the version's capacity, split into
+// blocks with their error correction, and interleaved
+fn codewords(data: &[u8], block_len: usize, blocks: usize, ec_len: usize) -> Vec<u8> {
+    let capacity = block_len * blocks;
+    let mut bits = Vec::with_capacity(capacity * 8);
+    let mut push = |value: u32, len: u32| {
+        for i in (0..len).rev() {
+            bits.push((value >> i) & 1 == 1);
+        }
+    };
+    push(0b0100, 4);
+    push(data.len() as u32, 8);
+    for &byte in data {
+        push(byte as u32, 8);
+    }
+    // up to 4 bits of terminator, then to a whole codeword
+    let terminator = (capacity * 8 - bits.len()).min(4);
+    bits.extend(std::iter::repeat_n(false, terminator));
+    bits.resize(bits.len().div_ceil(8) * 8, false);
+
+    let mut bytes: Vec<u8> = bits
+        .chunks(8)
+        .map(|byte| byte.iter().fold(0, |acc, &bit| (acc << 1) | bit as u8))
+        .collect();
+    for pad in [0xec, 0x11].into_iter().cycle() {
+        if bytes.len() >= capacity {
+            break;
+        }
+        bytes.push(pad);
+    }
+
+    let divisor = reed_solomon_divisor(ec_len);
+    let data_blocks: Vec<&[u8]> = bytes.chunks(block_len).collect();
+    let ec_blocks: Vec<Vec<u8>> = data_blocks
+        .iter()
+        .map(|block| reed_solomon_remainder(block, &divisor))
+        .collect();
+    let mut interleaved = Vec::with_capacity(capacity + ec_len * blocks);
+    for i in 0..block_len {
+        interleaved.extend(data_blocks.iter().map(|block| block[i]));
+    }
+    for i in 0..ec_len {
+        interleaved.extend(ec_blocks.iter().map(|block| block[i]));
+    }
+    interleaved
+}
+
+// multiplication in GF(2^8) modulo x^8 + x^4 + x^3 + x^2 + 1
+fn gf_mul(x: u8, y: u8) -> u8 {
+    let mut z: u32 = 0;
+    for i in (0..8).rev() {
+        z = (z << 1) ^ ((z >> 7) * 0x11d);
+        z ^= ((y as u32 >> i) & 1) * x as u32;
+    }
+    z as u8
+}
+
+// the generator polynomial's coefficients, highest power first and without
+// the leading 1
+fn reed_solomon_divisor(degree: usize) -> Vec<u8> {
+    let mut result = vec![0; degree];
+    result[degree - 1] = 1;
+    let mut root = 1;
+    for _ in 0..degree {
+        for j in 0..degree {
+            result[j] = gf_mul(result[j], root);
+            if j + 1 < degree {
+                result[j] ^= result[j + 1];
+            }
+        }
+        root = gf_mul(root, 0x02);
+    }
+    result
+}
+
+fn reed_solomon_remainder(data: &[u8], divisor: &[u8]) -> Vec<u8> {
+    let mut result = vec![0; divisor.len()];
+    for &byte in data {
+        let factor = byte ^ result.remove(0);
+        result.push(0);
+        for (x, &y) in result.iter_mut().zip(divisor) {
+            *x ^= gf_mul(y, factor);
+        }
+    }
+    result
+}
+
+// The code as it's built, with the finder, timing and format modules marked
+// so the data and masks go around them
+struct Matrix {
+    size: usize,
+    dark: Vec<bool>,
+    function: Vec<bool>,
+}
+
+impl Matrix {
+    fn set_function(&mut self, x: usize, y: usize, dark: bool) {
+        self.dark[y * self.size + x] = dark;
+        self.function[y * self.size + x] = true;
+    }
+
+    fn draw_function_patterns(&mut self, version: usize) {
+        let size = self.size;
+        for i in 0..size {
+            self.set_function(6, i, i % 2 == 0);
+            self.set_function(i, 6, i % 2 == 0);
+        }
+
+        // the finders in three corners, each with its light separator
+        for (cx, cy) in [(3, 3), (size - 4, 3), (3, size - 4)] {
+            for dy in -4i32..=4 {
+                for dx in -4i32..=4 {
+                    let (x, y) = (cx as i32 + dx, cy as i32 + dy);
+                    if (0..size as i32).contains(&x) && (0..size as i32).contains(&y) {
+                        let distance = dx.abs().max(dy.abs());
+                        self.set_function(x as usize, y as usize, distance != 2 && distance != 4);
+                    }
+                }
+            }
+        }
+
+        // up to version 6 there's one alignment pattern, the others it'd
+        // have would land on the finders
+        if version > 1 {
+            let center = 4 * version + 10;
+            for dy in -2i32..=2 {
+                for dx in -2i32..=2 {
+                    let (x, y) = (center as i32 + dx, center as i32 + dy);
+                    self.set_function(x as usize, y as usize, dx.abs().max(dy.abs()) != 1);
+                }
+            }
+        }
+
+        // reserved now, drawn for real once the mask's picked
+        self.draw_format_bits(0);
+    }
+
+    // The level and mask, twice over, and the dark module beside the bottom
+    // left finder. Level L is 01.
+    fn draw_format_bits(&mut self, mask: u8) {
+        let data = (1 << 3) | mask as u32;
+        let mut remainder = data;
+        for _ in 0..10 {
+            remainder = (remainder << 1) ^ ((remainder >> 9) * 0x537);
+        }
+        let bits = ((data << 10) | remainder) ^ 0x5412;
+        let bit = |i: usize| (bits >> i) & 1 == 1;
+
+        let size = self.size;
+        for i in 0..=5 {
+            self.set_function(8, i, bit(i));
+        }
+        self.set_function(8, 7, bit(6));
+        self.set_function(8, 8, bit(7));
+        self.set_function(7, 8, bit(8));
+        for i in 9..15 {
+            self.set_function(14 - i, 8, bit(i));
+        }
+        for i in 0..8 {
+            self.set_function(size - 1 - i, 8, bit(i));
+        }
+        for i in 8..15 {
+            self.set_function(8, size - 15 + i, bit(i));
+        }
+        self.set_function(8, size - 8, true);
+    }
+
+    // up and down two columns at a time from the bottom right, hopping over
+    // the vertical timing pattern. Whatever's left over stays light.
+    fn draw_codewords(&mut self, codewords: &[u8]) {
+        let size = self.size;
+        let mut i = 0;
+        let mut right = size - 1;
+        loop {
+            if right == 6 {
+                right = 5;
+            }
+            let upward = (right + 1) & 2 == 0;
+            for vertical in 0..size {
+                let y = if upward {
+                    size - 1 - vertical
+                } else {
+                    vertical
+                };
+                for x in [right, right - 1] {
+                    if !self.function[y * size + x] && i < codewords.len() * 8 {
+                        self.dark[y * size + x] = (codewords[i / 8] >> (7 - i % 8)) & 1 == 1;
+                        i += 1;
+                    }
+                }
+            }
+            if right < 2 {
+                break;
+            }
+            right -= 2;
+        }
+    }
+
+    // XORs, so applying it again takes it off
+    fn apply_mask(&mut self, mask: u8) {
+        let size = self.size;
+        for y in 0..size {
+            for x in 0..size {
+                let invert = match mask {
+                    0 => (x + y) % 2 == 0,
+                    1 => y % 2 == 0,
+                    2 => x % 3 == 0,
+                    3 => (x + y) % 3 == 0,
+                    4 => (x / 3 + y / 2) % 2 == 0,
+                    5 => x * y % 2 + x * y % 3 == 0,
+                    6 => (x * y % 2 + x * y % 3) % 2 == 0,
+                    _ => ((x + y) % 2 + x * y % 3) % 2 == 0,
+                };
+                if invert && !self.function[y * size + x] {
+                    self.dark[y * size + x] ^= true;
+                }
+            }
+        }
+    }
+
+    // the four penalty rules: long runs, 2x2 blocks, finder lookalikes and
+    // how far from half dark it is
+    fn penalty(&self) -> usize {
+        let size = self.size;
+        let dark = |x: usize, y: usize| self.dark[y * size + x];
+        let mut penalty = 0;
+
+        let lines = (0..size).flat_map(|i| {
+            [
+                (0..size).map(|j| dark(j, i)).collect::<Vec<_>>(),
+                (0..size).map(|j| dark(i, j)).collect::<Vec<_>>(),
+            ]
+        });
+        const FINDER: [bool; 11] = [
+            true, false, true, true, true, false, true, false, false, false, false,
+        ];
+        for line in lines {
+            let mut run = 1;
+            for j in 1..=size {
+                if j < size && line[j] == line[j - 1] {
+                    run += 1;
+                    continue;
+                }
+                if run >= 5 {
+                    penalty += run - 2;
+                }
+                run = 1;
+            }
+            for window in line.windows(FINDER.len()) {
+                if window == FINDER || window.iter().rev().eq(FINDER.iter()) {
+                    penalty += 40;
+                }
+            }
+        }
+
+        for y in 0..size - 1 {
+            for x in 0..size - 1 {
+                let color = dark(x, y);
+                if dark(x + 1, y) == color && dark(x, y + 1) == color && dark(x + 1, y + 1) == color
+                {
+                    penalty += 3;
+                }
+            }
+        }
+
+        let total = size * size;
+        let dark_count = self.dark.iter().filter(|&&dark| dark).count();
+        let percent = dark_count * 100 / total;
+        penalty + percent.abs_diff(50) / 5 * 10
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn modules(code: &QrCode) -> Vec<String> {
+        (0..code.size())
+            .map(|y| {
+                (0..code.size())
+                    .map(|x| if code.is_dark(x, y) { '#' } else { '.' })
+                    .collect()
+            })
+            .collect()
+    }
+
+    // the worked example from thonky.com's tutorial, HELLO WORLD at 1-M
+    #[test]
+    fn reed_solomon_matches_a_worked_example() {
+        let data = [
+            32, 91, 11, 120, 209, 114, 220, 77, 67, 64, 236, 17, 236, 17, 236, 17,
+        ];
+        let ec = reed_solomon_remainder(&data, &reed_solomon_divisor(10));
+        assert_eq!(ec, [196, 35, 39, 119, 235, 215, 231, 226, 93, 23]);
+    }
+
+    #[test]
+    fn codewords_are_the_header_bytes_and_padding() {
+        let codewords = codewords(b"hi", 19, 1, 7);
+        assert_eq!(codewords.len(), 26);
+        // 0100, a length of 2, h, i and the terminator
+        assert_eq!(codewords[..4], [0x40, 0x26, 0x86, 0x90]);
+        assert!(codewords[4..19].chunks(2).all(|pad| pad[0] == 0xec));
+        assert_eq!(codewords[5], 0x11);
+    }
+
+    // read back with a separate decoder when it was made, it picks mask 3
+    #[test]
+    fn encodes_a_version_1_link() {
+        let code = QrCode::encode(b"http://malter.lan").unwrap();
+        let expected = [
+            "#######.##....#######",
+            "#.....#..#....#.....#",
+            "#.###.#.###...#.###.#",
+            "#.###.#.####..#.###.#",
+            "#.###.#.#####.#.###.#",
+            "#.....#....#..#.....#",
+            "#######.#.#.#.#######",
+            "...........##........",
+            "####..#.#.####..###.#",
+            "#..##...#.#..########",
+            "#.##.##.##.#.###.#.##",
+            ".####..#....#..#.#.#.",
+            "....#.#.#.#.#.#.##..#",
+            "........###.#.#.#....",
+            "#######...##....#....",
+            "#.....#..#####..####.",
+            "#.###.#..#.....##.#.#",
+            "#.###.#.##..#.#......",
+            "#.###.#.#####..#..#..",
+            "#.....#.####.##.##..#",
+            "#######.##.#..#..##..",
+        ];
+        assert_eq!(modules(&code), expected);
+    }
+
+    #[test]
+    fn picks_the_smallest_version_that_fits() {
+        let size = |len| QrCode::encode(&vec![b'a'; len]).map(|code| code.size());
+        for (len, expected) in [
+            (0, 21),
+            (17, 21),
+            (18, 25),
+            (32, 25),
+            (33, 29),
+            (53, 29),
+            (54, 33),
+            (78, 33),
+            (79, 37),
+            (106, 37),
+            (107, 41),
+        ] {
+            assert_eq!(size(len), Some(expected), "{} bytes", len);
+        }
+    }
+
+    #[test]
+    fn max_bytes_is_the_limit() {
+        assert_eq!(QrCode::encode(&[b'a'; MAX_BYTES]).unwrap().size(), 41);
+        assert!(QrCode::encode(&[b'a'; MAX_BYTES + 1]).is_none());
+    }
+}
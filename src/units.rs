@@ -0,0 +1,60 @@
+//! Conversions between the units the sources report in and the ones shown
+
+/// Degrees celsius to fahrenheit
+pub fn c_to_f(celsius: f64) -> f64 {
+    celsius * 9.0 / 5.0 + 32.0
+}
+
+/// Degrees fahrenheit to celsius
+pub fn f_to_c(fahrenheit: f64) -> f64 {
+    (fahrenheit - 32.0) * 5.0 / 9.0
+}
+
+/// Hectopascals to inches of mercury
+pub fn hpa_to_inhg(hpa: f64) -> f64 {
+    hpa * 0.02953
+}
+
+/// Kilometres per hour to miles per hour
+pub fn kmh_to_mph(kmh: f64) -> f64 {
+    kmh / 1.609344
+}
+
+/// Metres per second to kilometres per hour
+pub fn ms_to_kmh(ms: f64) -> f64 {
+    ms * 3.6
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} is not {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn known_values() {
+        assert_close(c_to_f(0.0), 32.0);
+        assert_close(c_to_f(100.0), 212.0);
+        assert_close(c_to_f(-40.0), -40.0);
+        assert_close(f_to_c(98.6), 37.0);
+        assert_close(hpa_to_inhg(1013.25), 29.9212725);
+        assert_close(kmh_to_mph(160.9344), 100.0);
+        assert_close(ms_to_kmh(10.0), 36.0);
+    }
+
+    #[test]
+    fn round_trips() {
+        for value in [-40.0, -12.3, 0.0, 21.5, 37.0, 100.0] {
+            assert_close(f_to_c(c_to_f(value)), value);
+            assert_close(c_to_f(f_to_c(value)), value);
+            assert_close(kmh_to_mph(ms_to_kmh(value)) * 1.609344 / 3.6, value);
+        }
+    }
+}